@@ -1,47 +1,167 @@
-// use bump::BumpAllocator;
-// use linked_list::LinkListAllocator;
-use fixed_size_block::FixedSizeBlockAllocator;
+use crate::memory::{
+    self,
+    accounting::{self, Accounted, FrameOwner},
+    vma::{AreaKind, KERNEL_SPACE, VmError},
+    wx,
+};
+use crate::serial_println;
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use stats::{HeapStatistics, HeapStats};
 use x86_64::{
     VirtAddr,
-    structures::paging::{
-        FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB, mapper::MapToError,
-    },
+    structures::paging::{FrameAllocator, Mapper, Page, Size4KiB, mapper::MapToError},
 };
 pub mod bump;
+pub mod debug;
 pub mod fixed_size_block;
 pub mod linked_list;
+pub mod oom;
+pub mod slab;
+pub mod stats;
+pub mod trace;
+
+pub const HEAP_SIZE: usize = 100 * 1024; // 初始映射的堆大小 100 KiB
+pub const HEAP_MAX_SIZE: usize = 64 * 1024 * 1024; // 为堆预留的虚拟地址窗口 64 MiB
+const HEAP_GROW_STEP: usize = 64 * 1024; // 每次扩容至少映射 64 KiB
+
+/// 堆允许增长到的最大字节数，默认为整个虚拟地址窗口
+static HEAP_LIMIT: AtomicUsize = AtomicUsize::new(HEAP_MAX_SIZE);
+
+/// 内核堆的起始地址，`init_heap` 之前为 0
+static HEAP_START: AtomicUsize = AtomicUsize::new(0);
+
+/// 设置堆允许增长到的最大字节数，不会超过 `HEAP_MAX_SIZE`
+///
+/// 已经映射的部分不会因为上限变小而被回收
+pub fn set_heap_limit(limit: usize) {
+    HEAP_LIMIT.store(limit.min(HEAP_MAX_SIZE), Ordering::Relaxed);
+}
+
+/// 返回堆允许增长到的最大字节数
+pub fn heap_limit() -> usize {
+    HEAP_LIMIT.load(Ordering::Relaxed)
+}
+
+/// 从内核虚拟地址空间中预留堆窗口，映射最开始的 `HEAP_SIZE` 字节并初始化堆
+///
+/// 必须在 `memory::install` 之后调用
+pub fn init_heap() -> Result<(), VmError> {
+    let flags = wx::DATA_FLAGS;
+    // 堆的地址由虚拟地址空间管理器分配，不再使用写死的地址
+    let heap = KERNEL_SPACE
+        .lock()
+        .reserve(HEAP_MAX_SIZE as u64, AreaKind::Heap, flags)?;
+    let heap_start = heap.start.as_u64() as usize;
+    let heap_end = heap_start + HEAP_SIZE;
+    let mapped_end = memory::with_kernel_memory(|mapper, frame_allocator| {
+        map_heap_pages(heap_start, heap_end, mapper, frame_allocator)
+    })
+    .expect("memory::install must be called before init_heap");
+    if mapped_end != heap_end {
+        return Err(VmError::Map(MapToError::FrameAllocationFailed));
+    }
+    HEAP_START.store(heap_start, Ordering::Relaxed);
+    unsafe {
+        // 初始化堆，设置堆的开始地址和大小，.lock() 是为了获取锁，确保线程安全
+        ALLOCATOR.lock().init(heap_start, HEAP_SIZE);
+    }
+    // 内存不足时把完全空闲的块页还给后备分配器，让大的分配可以使用
+    #[cfg(feature = "heap-fixed-size-block")]
+    oom::register_reclaimer("fixed size block pages", || {
+        ALLOCATOR
+            .try_lock()
+            .map_or(0, |mut allocator| allocator.reclaim())
+    })
+    .expect("no reclaimers are registered before the heap");
+    Ok(())
+}
+
+/// 返回全局分配器当前的统计信息
+///
+/// 可能在内存不足的诊断路径上调用，所以只使用 `try_lock`，分配器正被占用时返回 None
+pub fn heap_stats() -> Option<HeapStats> {
+    Some(ALLOCATOR.try_lock()?.stats())
+}
+
+/// 把全局分配器的统计信息打印到串口
+pub fn report() {
+    match heap_stats() {
+        Some(stats) => serial_println!("{}", stats),
+        None => serial_println!("heap stats unavailable"),
+    }
+}
 
-pub const HEAP_START: usize = 0x_4444_4444_0000; // 可随意任取，只要它尚未用于其他内存区域
-pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+/// 堆空间不足时由分配器调用，在堆顶 `heap_top` 之后映射更多页面
+///
+/// 至少尝试映射 `min_size` 字节，但不会让堆超过 `heap_limit`。
+/// 返回新映射的字节数，它们紧跟在原来的堆顶之后，可以直接用于扩展堆
+fn grow_heap(heap_bottom: usize, heap_top: usize, min_size: usize) -> Option<usize> {
+    if heap_bottom == 0 || heap_bottom != HEAP_START.load(Ordering::Relaxed) {
+        // 堆还没有初始化，或者调用者不是内核堆，例如建立在静态缓冲区上的分配器
+        return None;
+    }
+    let limit_top = heap_bottom + heap_limit();
+    let wanted_top = align_up(heap_top + min_size.max(HEAP_GROW_STEP), 4096);
+    let new_top = wanted_top.min(limit_top);
+    if new_top < heap_top + min_size {
+        return None; // 达到了堆的上限
+    }
+    // 如果物理内存不足，只使用已经映射成功的部分
+    let mapped_top = memory::with_kernel_memory(|mapper, frame_allocator| {
+        map_heap_pages(heap_top, new_top, mapper, frame_allocator)
+    })?;
+    (mapped_top > heap_top).then(|| mapped_top - heap_top)
+}
 
-pub fn init_heap(
+/// 为堆区间 `[start, end)` 的每一页分配物理帧并映射
+///
+/// 返回实际映射到的位置，出错时它会小于 `end`
+fn map_heap_pages(
+    start: usize,
+    end: usize,
     mapper: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) -> Result<(), MapToError<Size4KiB>> {
-    let page_range = {
-        // 堆的开始地址
-        let heap_start = VirtAddr::new(HEAP_START as u64);
-        // 堆的结束地址， -1 是为了确保堆的结束地址是一个页的边界
-        let heap_end = heap_start + HEAP_SIZE - 1u64;
-        // 定义堆的开始页和结束页
-        let heap_start_page = Page::containing_address(heap_start);
-        let heap_end_page = Page::containing_address(heap_end);
-        // 定义堆的页范围
-        Page::range_inclusive(heap_start_page, heap_end_page)
-    };
-
-    for page in page_range {
-        let frame = frame_allocator
-            .allocate_frame() // 从帧分配器中分配一个物理帧
-            .ok_or(MapToError::FrameAllocationFailed)?; // 如果分配失败，返回错误
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() }; // 映射到页表中并刷新 TLB
+) -> usize {
+    let flags = wx::DATA_FLAGS;
+    let start_page = Page::containing_address(VirtAddr::new(start as u64));
+    let end_page = Page::containing_address(VirtAddr::new(end as u64 - 1));
+    for page in Page::range_inclusive(start_page, end_page) {
+        let mapped = frame_allocator.allocate_frame().and_then(|frame| {
+            let mut page_tables = Accounted::new(&mut *frame_allocator, FrameOwner::PageTable);
+            unsafe { mapper.map_to(page, frame, flags, &mut page_tables) }.ok()
+        });
+        match mapped {
+            Some(flush) => {
+                flush.flush(); // 刷新 TLB
+                accounting::charge(FrameOwner::Heap, 1);
+            }
+            None => return page.start_address().as_u64() as usize,
+        }
+    }
+    end
+}
+
+/// 无法原地调整大小时的 `realloc`：分配新的内存，复制内容后释放旧的内存
+///
+/// 和 `GlobalAlloc::realloc` 的默认实现相同，供覆盖了 `realloc` 的分配器回退使用。
+/// 调用时不能持有 `allocator` 的锁
+unsafe fn realloc_by_copy(
+    allocator: &impl GlobalAlloc,
+    ptr: *mut u8,
+    layout: Layout,
+    new_size: usize,
+) -> *mut u8 {
+    let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+    let new_ptr = unsafe { allocator.alloc(new_layout) };
+    if !new_ptr.is_null() {
         unsafe {
-            // 初始化堆，设置堆的开始地址和大小，.lock() 是为了获取锁，确保线程安全
-            ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+            ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            allocator.dealloc(ptr, layout);
         }
     }
-    Ok(())
+    new_ptr
 }
 
 /// 在 spin 外添加一个包装器，用于确保分配器是线程安全的
@@ -58,6 +178,10 @@ impl<A> Locked<A> {
     pub fn lock(&self) -> spin::MutexGuard<'_, A> {
         self.inner.lock()
     }
+
+    pub fn try_lock(&self) -> Option<spin::MutexGuard<'_, A>> {
+        self.inner.try_lock()
+    }
 }
 /// 向上对齐给定地址 `addr` 到对齐值 `align`
 fn align_up(addr: usize, align: usize) -> usize {
@@ -72,5 +196,58 @@ fn align_up(addr: usize, align: usize) -> usize {
     (addr + align - 1) & !(align - 1)
 }
 
+// 全局分配器由 Cargo feature 选择，默认是固定大小块分配器
+#[cfg(any(
+    all(feature = "heap-bump", feature = "heap-linked-list"),
+    all(feature = "heap-bump", feature = "heap-fixed-size-block"),
+    all(feature = "heap-linked-list", feature = "heap-fixed-size-block"),
+))]
+compile_error!("only one of the heap-* features can be enabled");
+#[cfg(not(any(
+    feature = "heap-bump",
+    feature = "heap-linked-list",
+    feature = "heap-fixed-size-block"
+)))]
+compile_error!("one of the heap-* features must be enabled to select the global allocator");
+
+#[cfg(feature = "heap-bump")]
+type HeapAllocator = bump::BumpAllocator;
+#[cfg(feature = "heap-linked-list")]
+type HeapAllocator = linked_list::LinkListAllocator;
+#[cfg(feature = "heap-fixed-size-block")]
+type HeapAllocator = fixed_size_block::FixedSizeBlockAllocator;
+
+/// 当前使用的全局分配器的名字
+#[cfg(feature = "heap-bump")]
+pub const ALLOCATOR_NAME: &str = "bump";
+#[cfg(feature = "heap-linked-list")]
+pub const ALLOCATOR_NAME: &str = "linked list";
+#[cfg(feature = "heap-fixed-size-block")]
+pub const ALLOCATOR_NAME: &str = "fixed size block";
+
+static ALLOCATOR: Locked<HeapAllocator> = Locked::new(HeapAllocator::new());
+
+/// 分配失败时先回收缓存再重试的内核堆，调试和追踪都包装在它外面
+type KernelHeap = oom::ReclaimingAllocator<Locked<HeapAllocator>>;
+
+#[cfg_attr(
+    not(any(feature = "heap-debug", feature = "heap-trace")),
+    global_allocator
+)]
+static KERNEL_HEAP: KernelHeap = oom::ReclaimingAllocator::new(&ALLOCATOR);
+
+// 堆调试模式：所有分配都经过红区、填充和释放检查，再交给选择的分配器
+#[cfg(feature = "heap-debug")]
+#[cfg_attr(not(feature = "heap-trace"), global_allocator)]
+static DEBUG_ALLOCATOR: debug::DebugAllocator<KernelHeap> =
+    debug::DebugAllocator::new(&KERNEL_HEAP);
+
+// 分配追踪放在最外层，记录的是调用者看到的指针和布局
+#[cfg(all(feature = "heap-trace", feature = "heap-debug"))]
+#[global_allocator]
+static TRACE_ALLOCATOR: trace::TracingAllocator<debug::DebugAllocator<KernelHeap>> =
+    trace::TracingAllocator::new(&DEBUG_ALLOCATOR);
+#[cfg(all(feature = "heap-trace", not(feature = "heap-debug")))]
 #[global_allocator]
-static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
+static TRACE_ALLOCATOR: trace::TracingAllocator<KernelHeap> =
+    trace::TracingAllocator::new(&KERNEL_HEAP);
@@ -1,3 +1,4 @@
+use super::stats::{Counters, HeapStatistics, HeapStats};
 use super::{Locked, align_up};
 use alloc::alloc::{GlobalAlloc, Layout};
 use core::ptr;
@@ -6,6 +7,7 @@ pub struct BumpAllocator {
     heap_end: usize,
     next: usize,
     allocations: usize,
+    counters: Counters,
 }
 impl BumpAllocator {
     /// 创建一个新的空的 bump 分配器
@@ -15,6 +17,7 @@ impl BumpAllocator {
             heap_end: 0,    // 堆的结束位置
             next: 0,        // 下一个分配的地址
             allocations: 0, // 已分配的内存块数量
+            counters: Counters::new(),
         }
     }
 
@@ -26,6 +29,31 @@ impl BumpAllocator {
         self.heap_end = heap_start + heap_size;
         self.next = heap_start; // 下一个分配的地址从 堆的起始位置 开始
     }
+
+    /// 映射更多页面，让堆至少延伸到 `end`，新页面紧跟在堆的结束位置之后
+    ///
+    /// 堆达到上限或物理内存不足时返回 false
+    fn grow_to(&mut self, end: usize) -> bool {
+        if end <= self.heap_end {
+            return true;
+        }
+        match super::grow_heap(self.heap_start, self.heap_end, end - self.heap_end) {
+            Some(grown) => self.heap_end += grown,
+            None => return false, // 内存不足
+        }
+        end <= self.heap_end // 物理内存不足时只扩展了一部分
+    }
+}
+
+impl HeapStatistics for BumpAllocator {
+    fn stats(&self) -> HeapStats {
+        let mut stats = self.counters.to_stats("bump");
+        stats.heap_size = self.heap_end - self.heap_start;
+        // 只有 next 之后的部分能被分配，释放的内存要等所有分配都释放后才能重用
+        stats.free_bytes = self.heap_end - self.next;
+        stats.largest_free_region = stats.free_bytes;
+        stats
+    }
 }
 
 unsafe impl GlobalAlloc for Locked<BumpAllocator> {
@@ -41,21 +69,47 @@ unsafe impl GlobalAlloc for Locked<BumpAllocator> {
             None => return ptr::null_mut(),
         };
 
-        if alloc_end > bump.heap_end {
-            ptr::null_mut() // 内存不足
-        } else {
-            bump.next = alloc_end;
-            bump.allocations += 1;
-            alloc_start as *mut u8
+        if !bump.grow_to(alloc_end) {
+            return ptr::null_mut();
         }
+        bump.next = alloc_end;
+        bump.allocations += 1;
+        bump.counters.record_alloc(layout.size());
+        alloc_start as *mut u8
     }
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
+    unsafe fn dealloc(&self, _ptr: *mut u8, layout: Layout) {
         let mut bump = self.lock();
 
+        bump.counters.record_free(layout.size());
         bump.allocations -= 1;
         if bump.allocations == 0 {
             bump.next = bump.heap_start; // 重置下一个分配的地址到堆的起始位置
         }
     }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let mut bump = self.lock();
+        let start = ptr as usize;
+        if start + layout.size() == bump.next {
+            // 最后一次分配 -> 直接移动 next，增长时如有需要扩展堆
+            let new_end = match start.checked_add(new_size) {
+                Some(end) => end,
+                None => return ptr::null_mut(),
+            };
+            if !bump.grow_to(new_end) {
+                return ptr::null_mut();
+            }
+            bump.next = new_end;
+            bump.counters.record_resize(layout.size(), new_size);
+            return ptr;
+        }
+        if new_size <= layout.size() {
+            // 缩小时多出的部分和释放的内存一样，要等所有分配都释放后才能重用
+            bump.counters.record_resize(layout.size(), new_size);
+            return ptr;
+        }
+        drop(bump);
+        unsafe { super::realloc_by_copy(self, ptr, layout, new_size) }
+    }
 }
@@ -0,0 +1,163 @@
+use crate::serial_println;
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::{mem, ptr};
+
+/// 用户内存前后红区的字节数
+const RED_ZONE_SIZE: usize = 16;
+
+/// 红区填充的字节，被改写说明发生了越界写
+const RED_ZONE_BYTE: u8 = 0xfd;
+/// 新分配的内存填充的字节，读到它说明使用了未初始化的内存
+const ALLOC_POISON: u8 = 0xcd;
+/// 释放的内存填充的字节，读到它说明使用了已经释放的内存
+const FREE_POISON: u8 = 0xdd;
+
+/// 头部中表示分配状态的魔数
+const MAGIC_ALLOCATED: usize = 0xa110_ca7e_d0c0_ffee;
+const MAGIC_FREED: usize = 0xf4ee_d0c0_dead_beef;
+
+/// 放在前红区之前的头部，记录分配时的布局
+///
+/// 魔数放在最后，内层分配器释放后在块开头写入的链表节点不会覆盖它，
+/// 在内存被重新分配之前都能检测到重复释放
+#[repr(C)]
+struct Header {
+    size: usize,  // 分配时请求的大小
+    align: usize, // 分配时请求的对齐
+    magic: usize, // MAGIC_ALLOCATED 或 MAGIC_FREED
+}
+
+/// 头部和前红区加起来的大小，头部紧挨着前红区
+const HEADER_AND_RED_ZONE: usize = mem::size_of::<Header>() + RED_ZONE_SIZE;
+
+/// 内层分配至少使用的对齐，保证头部按 usize 对齐
+const MIN_ALIGN: usize = 16;
+
+/// 已经检测到的问题数量
+static ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+/// 返回堆调试模式已经检测到的问题数量
+pub fn errors() -> usize {
+    ERRORS.load(Ordering::Relaxed)
+}
+
+/// 包装另一个分配器的堆调试分配器
+///
+/// 每个分配的布局是：头部、前红区、用户内存、后红区。
+/// 分配时用户内存填充 `ALLOC_POISON`，释放时填充 `FREE_POISON`；
+/// 释放时检查重复释放、`Layout` 不一致和红区是否被改写，
+/// 发现问题时把出问题的地址打印到串口。
+/// 启用 `heap-debug` feature 后全局分配器会被包装在它里面
+pub struct DebugAllocator<A: 'static> {
+    inner: &'static A,
+}
+
+impl<A> DebugAllocator<A> {
+    pub const fn new(inner: &'static A) -> Self {
+        DebugAllocator { inner }
+    }
+}
+
+/// 用户请求的布局对应的内层布局，以及用户内存相对于内层块起始地址的偏移
+fn inner_layout(size: usize, align: usize) -> Option<(Layout, usize)> {
+    let align = align.max(MIN_ALIGN);
+    let front = HEADER_AND_RED_ZONE.next_multiple_of(align);
+    let size = front.checked_add(size)?.checked_add(RED_ZONE_SIZE)?;
+    let layout = Layout::from_size_align(size, align).ok()?;
+    Some((layout, front))
+}
+
+/// 用户内存 `ptr` 对应的头部
+fn header(ptr: *mut u8) -> *mut Header {
+    ptr.wrapping_sub(HEADER_AND_RED_ZONE) as *mut Header
+}
+
+/// 记录并报告一个问题
+fn report(args: core::fmt::Arguments) {
+    ERRORS.fetch_add(1, Ordering::Relaxed);
+    serial_println!("heap-debug: {}", args);
+}
+
+/// 检查从 `start` 开始的红区，返回第一个被改写的字节的偏移
+///
+/// 这个函数是不安全的，因为 `start` 开始的 `RED_ZONE_SIZE` 字节必须可读
+unsafe fn check_red_zone(start: *const u8) -> Option<usize> {
+    (0..RED_ZONE_SIZE).find(|&offset| unsafe { start.add(offset).read() } != RED_ZONE_BYTE)
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for DebugAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some((inner, front)) = inner_layout(layout.size(), layout.align()) else {
+            return ptr::null_mut();
+        };
+        let base = unsafe { self.inner.alloc(inner) };
+        if base.is_null() {
+            return ptr::null_mut();
+        }
+        unsafe {
+            let ptr = base.add(front);
+            header(ptr).write(Header {
+                size: layout.size(),
+                align: layout.align(),
+                magic: MAGIC_ALLOCATED,
+            });
+            ptr.sub(RED_ZONE_SIZE)
+                .write_bytes(RED_ZONE_BYTE, RED_ZONE_SIZE);
+            ptr.write_bytes(ALLOC_POISON, layout.size());
+            ptr.add(layout.size())
+                .write_bytes(RED_ZONE_BYTE, RED_ZONE_SIZE);
+            ptr
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let header = unsafe { &mut *header(ptr) };
+        match header.magic {
+            MAGIC_ALLOCATED => {}
+            MAGIC_FREED => {
+                // 再释放一次会破坏内层分配器的空闲链表，宁可泄漏
+                report(format_args!("double free of {:p} ({:?})", ptr, layout));
+                return;
+            }
+            _ => {
+                report(format_args!(
+                    "free of {:p} ({:?}) which was not allocated or whose header was overwritten",
+                    ptr, layout
+                ));
+                return;
+            }
+        }
+        if header.size != layout.size() || header.align != layout.align() {
+            report(format_args!(
+                "layout mismatch when freeing {:p}: allocated with size {} align {}, freed with {:?}",
+                ptr, header.size, header.align, layout
+            ));
+        }
+        // 之后都按分配时记录的布局处理，这样内层分配器拿回的还是原来的块
+        let (size, align) = (header.size, header.align);
+        unsafe {
+            if let Some(offset) = check_red_zone(ptr.sub(RED_ZONE_SIZE)) {
+                report(format_args!(
+                    "buffer underflow before {:p}: red zone byte {} bytes before it was overwritten",
+                    ptr,
+                    RED_ZONE_SIZE - offset
+                ));
+            }
+            if let Some(offset) = check_red_zone(ptr.add(size)) {
+                report(format_args!(
+                    "buffer overflow after {:p} (size {}): byte at offset {} was overwritten",
+                    ptr,
+                    size,
+                    size + offset
+                ));
+            }
+        }
+        header.magic = MAGIC_FREED;
+        let (inner, front) = inner_layout(size, align).unwrap();
+        unsafe {
+            ptr.write_bytes(FREE_POISON, size);
+            self.inner.dealloc(ptr.sub(front), inner);
+        }
+    }
+}
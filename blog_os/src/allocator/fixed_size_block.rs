@@ -1,6 +1,8 @@
 use super::Locked;
+use super::linked_list::LinkListAllocator;
+use super::stats::{Counters, HeapStatistics, HeapStats};
 use alloc::alloc::{GlobalAlloc, Layout};
-use core::{mem, ptr, ptr::NonNull};
+use core::{mem, ptr};
 struct ListNode {
     next: Option<&'static mut ListNode>,
 }
@@ -9,11 +11,35 @@ struct ListNode {
 ///
 /// 各块大小必须为2的幂，因为它们同时被
 /// 用作块内存对齐（对齐方式必须始终为2的幂）
-const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+pub const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// 空闲链表为空时，从后备分配器取出一整页切成块
+const PAGE_SIZE: usize = 4096;
+
+/// 最多能跟踪的页数，覆盖整个堆窗口
+const MAX_PAGES: usize = super::HEAP_MAX_SIZE / PAGE_SIZE;
+
+/// 一个切成块的页的状态
+#[derive(Clone, Copy)]
+struct PageInfo {
+    class: u8, // 块大小在 `BLOCK_SIZES` 中的索引加 1，0 表示这一页没有切成块
+    free: u16, // 这一页中空闲的块数
+}
+
+impl PageInfo {
+    const UNUSED: PageInfo = PageInfo { class: 0, free: 0 };
+}
 
 pub struct FixedSizeBlockAllocator {
     list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
-    fallback_allocator: linked_list_allocator::Heap,
+    fallback_allocator: LinkListAllocator,
+    pages: [PageInfo; MAX_PAGES], // 堆中每一页的状态，下标是相对于堆底的页号
+    counters: Counters,
+    class_hits: [usize; BLOCK_SIZES.len()], // 每个块大小直接从链表取到块的次数
+    class_refills: [usize; BLOCK_SIZES.len()], // 每个块大小切分整页补充链表的次数
+    class_pages: [usize; BLOCK_SIZES.len()], // 每个块大小当前持有的页数
+    free_blocks: [usize; BLOCK_SIZES.len()], // 每个块大小空闲链表中的块数
+    fallback_allocations: usize,            // 直接由后备分配器满足的分配次数
 }
 
 impl FixedSizeBlockAllocator {
@@ -22,7 +48,14 @@ impl FixedSizeBlockAllocator {
         const EMPTY: Option<&'static mut ListNode> = None;
         FixedSizeBlockAllocator {
             list_heads: [EMPTY; BLOCK_SIZES.len()], // 每个块大小对应一个链表头
-            fallback_allocator: linked_list_allocator::Heap::empty(), // 后备分配器，用于分配无法满足的请求
+            fallback_allocator: LinkListAllocator::new(), // 后备分配器，用于分配无法满足的请求
+            pages: [PageInfo::UNUSED; MAX_PAGES],
+            counters: Counters::new(),
+            class_hits: [0; BLOCK_SIZES.len()],
+            class_refills: [0; BLOCK_SIZES.len()],
+            class_pages: [0; BLOCK_SIZES.len()],
+            free_blocks: [0; BLOCK_SIZES.len()],
+            fallback_allocations: 0,
         }
     }
     /// 用给定的堆边界初始化分配器
@@ -30,21 +63,135 @@ impl FixedSizeBlockAllocator {
     /// 此函数是不安全的，因为调用者必须保证给定的堆边界是有效的且堆是
     /// 未使用的。此方法只能调用一次。
     pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        // 页的状态表按堆窗口的大小分配
+        assert!(heap_size <= super::HEAP_MAX_SIZE);
         unsafe {
             self.fallback_allocator.init(heap_start, heap_size);
         }
     }
     /// 使用后备分配器分配
+    ///
+    /// 后备堆空间不足时，后备分配器会映射更多页面扩展堆，然后重试一次
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
-        // allocate_first_fit的作用是在后备分配器中查找第一个合适的空闲块
-        // 并将其分配给请求的布局。如果没有合适的空闲块，返回null指针。
-        match self.fallback_allocator.allocate_first_fit(layout) {
-            Ok(ptr) => ptr.as_ptr(),
-            Err(_) => ptr::null_mut(),
+        self.fallback_allocator.allocate(layout)
+    }
+
+    /// 把所有块都空闲的页还给后备分配器，返回释放的字节数
+    ///
+    /// 释放块时已经会回收多余的空闲页，但每个块大小会保留最后一页，
+    /// 避免反复切分和回收。内存不足时可以调用这个方法把它们也交出去
+    pub fn reclaim(&mut self) -> usize {
+        let mut released = 0;
+        for page_index in 0..MAX_PAGES {
+            let info = self.pages[page_index];
+            if info.class == 0 {
+                continue;
+            }
+            let index = info.class as usize - 1;
+            if info.free as usize == blocks_per_page(index) {
+                let page = self.page_base() + page_index * PAGE_SIZE;
+                self.release_page(index, page);
+                released += PAGE_SIZE;
+            }
+        }
+        released
+    }
+
+    /// 页号的基准地址，也就是堆底所在的页
+    fn page_base(&self) -> usize {
+        self.fallback_allocator.heap_start() & !(PAGE_SIZE - 1)
+    }
+
+    /// 包含 `addr` 的页的状态
+    fn page_info(&mut self, addr: usize) -> &mut PageInfo {
+        let page_index = (addr - self.page_base()) / PAGE_SIZE;
+        &mut self.pages[page_index]
+    }
+
+    /// 从后备分配器取出一整页，切成 `index` 对应大小的块放入空闲链表
+    ///
+    /// 后备分配器也无法满足时返回 false
+    fn refill(&mut self, index: usize) -> bool {
+        let page_layout = Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap();
+        let page = self.fallback_alloc(page_layout) as usize;
+        if page == 0 {
+            return false;
+        }
+        let blocks = blocks_per_page(index);
+        *self.page_info(page) = PageInfo {
+            class: index as u8 + 1,
+            free: blocks as u16,
+        };
+        // 从后往前压入链表，这样分配时按地址从小到大
+        for block in (0..blocks).rev() {
+            let node_ptr = (page + block * BLOCK_SIZES[index]) as *mut ListNode;
+            unsafe {
+                node_ptr.write(ListNode {
+                    next: self.list_heads[index].take(),
+                });
+                self.list_heads[index] = Some(&mut *node_ptr);
+            }
+        }
+        self.free_blocks[index] += blocks;
+        self.class_pages[index] += 1;
+        self.class_refills[index] += 1;
+        true
+    }
+
+    /// 把 `page` 中的块全部从空闲链表中摘下，再把这一页还给后备分配器
+    ///
+    /// 调用者必须保证这一页的所有块都是空闲的
+    fn release_page(&mut self, index: usize, page: usize) {
+        let mut link = &mut self.list_heads[index];
+        while let Some(node) = link.take() {
+            let addr = node as *mut ListNode as usize;
+            if (page..page + PAGE_SIZE).contains(&addr) {
+                *link = node.next.take(); // 属于这一页 -> 摘下
+            } else {
+                link = &mut link.insert(node).next;
+            }
+        }
+        *self.page_info(page) = PageInfo::UNUSED;
+        self.free_blocks[index] -= blocks_per_page(index);
+        self.class_pages[index] -= 1;
+        let page_layout = Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap();
+        unsafe {
+            self.fallback_allocator
+                .deallocate(page as *mut u8, page_layout)
+        };
+    }
+}
+
+impl HeapStatistics for FixedSizeBlockAllocator {
+    fn stats(&self) -> HeapStats {
+        let mut stats = self.counters.to_stats("fixed size block");
+        // 后备分配器的空闲链表可以直接遍历，不需要试探分配
+        let fallback = self.fallback_allocator.stats();
+        stats.heap_size = fallback.heap_size;
+        stats.fallback_allocations = self.fallback_allocations;
+        stats.fallback_bytes_in_use = fallback.bytes_in_use;
+        stats.free_bytes = fallback.free_bytes;
+        stats.largest_free_region = fallback.largest_free_region;
+        for (index, class) in stats.size_classes.iter_mut().enumerate() {
+            class.hits = self.class_hits[index];
+            class.refills = self.class_refills[index];
+            class.pages = self.class_pages[index];
+            class.free_blocks = self.free_blocks[index];
+            stats.free_bytes += class.free_blocks * class.block_size;
+            if class.free_blocks > 0 {
+                // 空闲的块也能满足不超过块大小的分配
+                stats.largest_free_region = stats.largest_free_region.max(class.block_size);
+            }
         }
+        stats
     }
 }
 
+/// 一页能切出的 `index` 对应大小的块数
+fn blocks_per_page(index: usize) -> usize {
+    PAGE_SIZE / BLOCK_SIZES[index]
+}
+
 /// 为给定布局选择适当的块大小
 ///
 /// 返回 `BLOCK_SIZES` 数组中的索引
@@ -57,27 +204,40 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut allocator = self.lock();
         // 查找适当的块大小索引
-        match list_index(&layout) {
+        let ptr = match list_index(&layout) {
             // 如果找到合适的块大小索引，从对应链表中弹出一个节点
-            Some(index) => match allocator.list_heads[index].take() {
-                // 如果链表不为空，弹出一个节点并返回其指针
-                Some(node) => {
-                    allocator.list_heads[index] = node.next.take();
-                    node as *mut ListNode as *mut u8
+            Some(index) => {
+                if allocator.list_heads[index].is_some() {
+                    allocator.class_hits[index] += 1;
+                } else if !allocator.refill(index) {
+                    // 如果链表为空，切分一整页补充链表；后备分配器也无法满足请求时返回null指针
+                    return ptr::null_mut();
                 }
-                // 如果链表为空，尝试从后备分配器分配一个块
-                None => {
-                    let block_size = BLOCK_SIZES[index];
-                    let block_align = block_size; // 块对齐方式与块大小相同
-                    let layout = Layout::from_size_align(block_size, block_align).unwrap();
-                    allocator.fallback_alloc(layout) // 如果后备分配器也无法满足请求，返回null指针
+                // 链表不为空，弹出一个节点并返回其指针
+                let node = allocator.list_heads[index].take().unwrap();
+                allocator.list_heads[index] = node.next.take();
+                let ptr = node as *mut ListNode as *mut u8;
+                allocator.free_blocks[index] -= 1;
+                allocator.page_info(ptr as usize).free -= 1;
+                ptr
+            }
+            // 如果没有合适的块大小索引，尝试从后备分配器分配
+            None => {
+                let ptr = allocator.fallback_alloc(layout);
+                if !ptr.is_null() {
+                    allocator.fallback_allocations += 1;
                 }
-            },
-            None => allocator.fallback_alloc(layout), // 如果没有合适的块大小索引，尝试从后备分配器分配
+                ptr
+            }
+        };
+        if !ptr.is_null() {
+            allocator.counters.record_alloc(layout.size());
         }
+        ptr
     }
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let mut allocator = self.lock();
+        allocator.counters.record_free(layout.size());
         match list_index(&layout) {
             Some(index) => {
                 let new_node = ListNode {
@@ -91,10 +251,18 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
                     new_node_ptr.write(new_node); // 将新节点写入块内存
                     allocator.list_heads[index] = Some(&mut *new_node_ptr); // 将新节点设置为链表头
                 }
+                allocator.free_blocks[index] += 1;
+                let page_info = allocator.page_info(ptr as usize);
+                page_info.free += 1;
+                let page_free = page_info.free as usize;
+                // 整页空闲，并且去掉这一页后链表中还有至少一页的空闲块 -> 还给后备分配器，
+                // 这样某个块大小的用量高峰过去后，内存可以被其他块大小和大分配重用
+                let blocks = blocks_per_page(index);
+                if page_free == blocks && allocator.free_blocks[index] >= 2 * blocks {
+                    allocator.release_page(index, ptr as usize & !(PAGE_SIZE - 1));
+                }
             }
             None => {
-                // 将指针转换为NonNull类型，unwrap()确保指针不为null
-                let ptr = NonNull::new(ptr).unwrap();
                 unsafe {
                     // deallocate 从后备分配器中释放指针
                     allocator.fallback_allocator.deallocate(ptr, layout);
@@ -102,4 +270,24 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
             }
         }
     }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        if let Some(index) = list_index(&layout)
+            && list_index(&new_layout) == Some(index)
+        {
+            // 新的大小仍然属于同一个块大小，块本身就放得下
+            self.lock().counters.record_resize(layout.size(), new_size);
+            return ptr;
+        }
+        if list_index(&layout).is_none() && list_index(&new_layout).is_none() {
+            // 两个大小都由后备分配器负责，尝试占用后面的空闲区域或者把多出的部分还回去
+            let mut allocator = self.lock();
+            if allocator.fallback_allocator.resize(ptr, layout, new_size) {
+                allocator.counters.record_resize(layout.size(), new_size);
+                return ptr;
+            }
+        }
+        unsafe { super::realloc_by_copy(self, ptr, layout, new_size) }
+    }
 }
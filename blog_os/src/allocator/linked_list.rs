@@ -1,3 +1,4 @@
+use super::stats::{Counters, HeapStatistics, HeapStats};
 use super::{Locked, align_up};
 use alloc::alloc::{GlobalAlloc, Layout};
 use core::{mem, ptr};
@@ -22,68 +23,264 @@ impl ListNode {
     }
 }
 
+/// 在空闲链表中选择区域的策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlacementPolicy {
+    FirstFit, // 第一个放得下的区域，速度快，但小的空隙会堆积在堆的开头
+    BestFit,  // 放得下的区域中最小的一个，留下的空隙最小，但每次都要遍历整个链表
+    NextFit,  // 从上一次分配的位置继续查找第一个放得下的区域，分配更均匀地分布在堆中
+}
+
 pub struct LinkListAllocator {
     head: ListNode,
+    heap_start: usize, // 堆的起始位置
+    heap_end: usize,   // 堆的结束位置，扩容时新页面从这里开始
+    policy: PlacementPolicy,
+    next_fit_start: usize, // next-fit 下一次开始查找的地址
+    counters: Counters,
 }
 impl LinkListAllocator {
-    /// 创建一个空的 链表分配器
+    /// 创建一个空的 链表分配器，使用 first-fit 策略
     pub const fn new() -> Self {
+        Self::with_policy(PlacementPolicy::FirstFit)
+    }
+
+    /// 创建一个使用给定策略的空的 链表分配器
+    pub const fn with_policy(policy: PlacementPolicy) -> Self {
         LinkListAllocator {
             head: ListNode::new(0),
+            heap_start: 0,
+            heap_end: 0,
+            policy,
+            next_fit_start: 0,
+            counters: Counters::new(),
         }
     }
 
+    /// 当前的选择策略
+    pub fn policy(&self) -> PlacementPolicy {
+        self.policy
+    }
+
+    /// 修改选择策略，只影响之后的分配
+    pub fn set_policy(&mut self, policy: PlacementPolicy) {
+        self.policy = policy;
+    }
+
     /// 用给定的堆边界初始化分配器
     ///
     /// 这个函数是不安全的，因为调用者必须保证给定的堆边界是有效的并且堆是未使用的。
     /// 此方法只能调用一次
     pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.heap_start = heap_start;
+        self.heap_end = heap_start + heap_size;
         unsafe {
             self.add_free_region(heap_start, heap_size);
         }
     }
 
-    /// 将给定的内存区域添加到链表前端
+    /// 映射至少 `min_size` 字节的新页面，并作为空闲区域加入链表
+    ///
+    /// 堆达到上限或物理内存不足时返回 false
+    fn grow(&mut self, min_size: usize) -> bool {
+        match super::grow_heap(self.heap_start, self.heap_end, min_size) {
+            Some(grown) => {
+                // 新页面按页对齐，足以存储 ListNode
+                unsafe { self.add_free_region(self.heap_end, grown) };
+                self.heap_end += grown;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 将给定的内存区域按地址顺序插入链表，并和相邻的空闲区域合并
+    ///
+    /// 链表始终按地址从小到大排列，所以只需要检查插入位置前后的两个节点
     unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
         // 确保给定的内存区域足以存储 ListNode
         assert_eq!(align_up(addr, mem::align_of::<ListNode>()), addr);
         assert!(size >= mem::size_of::<ListNode>());
 
-        // 创建一个新的 ListNode 并将其添加到链表前端
-        let mut node = ListNode::new(size);
-        node.next = self.head.next.take();
-        // 将 addr 转换为 ListNode 指针
-        let node_ptr = addr as *mut ListNode;
-        unsafe {
-            node_ptr.write(node); // 写入新的 ListNode
-            self.head.next = Some(&mut *node_ptr);
+        // 找到最后一个起始地址小于 addr 的节点，新区域插入到它后面
+        let head = &self.head as *const ListNode;
+        let mut current = &mut self.head;
+        while current
+            .next
+            .as_ref()
+            .is_some_and(|next| next.start_addr() < addr)
+        {
+            current = current.next.as_mut().unwrap();
+        }
+        let is_head = ptr::eq(current, head);
+        debug_assert!(
+            is_head || current.end_addr() <= addr,
+            "freed region overlaps a free region"
+        );
+        debug_assert!(
+            current
+                .next
+                .as_ref()
+                .is_none_or(|next| addr + size <= next.start_addr()),
+            "freed region overlaps a free region"
+        );
+
+        if !is_head && current.end_addr() == addr {
+            // 紧跟在前一个区域之后 -> 直接扩大前一个区域
+            current.size += size;
+        } else {
+            // 创建一个新的 ListNode 并插入到 current 后面
+            let mut node = ListNode::new(size);
+            node.next = current.next.take();
+            // 将 addr 转换为 ListNode 指针
+            let node_ptr = addr as *mut ListNode;
+            unsafe {
+                node_ptr.write(node); // 写入新的 ListNode
+                current.next = Some(&mut *node_ptr);
+            }
+            current = current.next.as_mut().unwrap();
+        }
+
+        // 和后一个区域相邻 -> 把后一个区域合并进来
+        if let Some(next) = current.next.take() {
+            if current.end_addr() == next.start_addr() {
+                current.size += next.size;
+                current.next = next.next.take();
+            } else {
+                current.next = Some(next);
+            }
+        }
+    }
+
+    /// 空闲链表中的区域数量，完全释放的堆应该只剩一个区域
+    pub fn free_regions(&self) -> usize {
+        let mut count = 0;
+        let mut current = self.head.next.as_deref();
+        while let Some(region) = current {
+            count += 1;
+            current = region.next.as_deref();
         }
+        count
     }
-    /// 查找给定大小和对齐方式的空闲区域并将其从链表中移除。
+
+    /// 尝试原地把 `addr` 处大小为 `old_size` 的分配调整为 `new_size`，两个大小都已经由
+    /// `size_align` 调整过
+    ///
+    /// 缩小时把多出的部分还回链表；增长时占用紧跟在分配后面的空闲区域。
+    /// 多出或剩下的部分太小、放不下 ListNode 时无法原地调整，返回 false
+    fn resize_in_place(&mut self, addr: usize, old_size: usize, new_size: usize) -> bool {
+        let old_end = addr + old_size;
+        let new_end = addr + new_size;
+        if new_size <= old_size {
+            let excess_size = old_size - new_size;
+            if excess_size == 0 {
+                return true;
+            }
+            if excess_size < mem::size_of::<ListNode>() {
+                return false;
+            }
+            unsafe { self.add_free_region(new_end, excess_size) };
+            return true;
+        }
+
+        // 链表按地址排列，找到起始地址不小于分配结束位置的第一个区域
+        let mut current = &mut self.head;
+        while current
+            .next
+            .as_ref()
+            .is_some_and(|next| next.start_addr() < old_end)
+        {
+            current = current.next.as_mut().unwrap();
+        }
+        let Some(next) = current.next.as_ref() else {
+            return false;
+        };
+        if next.start_addr() != old_end || next.end_addr() < new_end {
+            return false; // 后面不是空闲区域，或者空闲区域不够大
+        }
+        let excess_size = next.end_addr() - new_end;
+        if excess_size > 0 && excess_size < mem::size_of::<ListNode>() {
+            return false;
+        }
+        // 从链表中移除后面的区域，剩余部分再还回链表
+        let region = current.next.take().unwrap();
+        current.next = region.next.take();
+        if excess_size > 0 {
+            unsafe { self.add_free_region(new_end, excess_size) };
+        }
+        true
+    }
+
+    /// 按选择策略查找给定大小和对齐方式的空闲区域并将其从链表中移除。
     ///
     /// 返回一个包含链表节点和分配内存区域起始地址的元组。
     fn find_region(&mut self, size: usize, align: usize) -> Option<(&'static mut ListNode, usize)> {
+        let (region_start, alloc_start) = self.choose_region(size, align)?;
         let mut current = &mut self.head;
-        while let Some(ref mut region) = current.next {
-            if let Ok(alloc_start) = Self::alloc_from_region(&region, size, align) {
-                // 区域适用于分配 -> 从链表中移除该节点
-                let next = region.next.take();
-                let ret = Some((current.next.take().unwrap(), alloc_start));
-                current.next = next;
-                return ret;
-            } else {
-                // 区域不适用 -> 继续下一个区域
-                current = current.next.as_mut().unwrap();
+        while current
+            .next
+            .as_ref()
+            .is_some_and(|next| next.start_addr() != region_start)
+        {
+            current = current.next.as_mut().unwrap();
+        }
+        // 区域适用于分配 -> 从链表中移除该节点
+        let region = current.next.take().unwrap();
+        current.next = region.next.take();
+        self.next_fit_start = alloc_start + size;
+        Some((region, alloc_start))
+    }
+
+    /// 按选择策略挑出一个放得下的区域
+    ///
+    /// 返回区域的起始地址和分配内存区域的起始地址
+    fn choose_region(&self, size: usize, align: usize) -> Option<(usize, usize)> {
+        let mut best: Option<(usize, (usize, usize))> = None; // best-fit 目前最小的区域
+        let mut first = None; // 第一个放得下的区域，next-fit 回绕时使用
+        let mut current = self.head.next.as_deref();
+        while let Some(region) = current {
+            current = region.next.as_deref();
+            let Ok(alloc_start) = Self::alloc_from_region(region, size, align) else {
+                continue; // 区域不适用 -> 继续下一个区域
+            };
+            let found = (region.start_addr(), alloc_start);
+            match self.policy {
+                PlacementPolicy::FirstFit => return Some(found),
+                PlacementPolicy::BestFit => {
+                    if region.size == size {
+                        return Some(found); // 正好放下，不会有更好的区域
+                    }
+                    if best.is_none_or(|(best_size, _)| region.size < best_size) {
+                        best = Some((region.size, found));
+                    }
+                }
+                PlacementPolicy::NextFit => {
+                    if region.end_addr() > self.next_fit_start {
+                        return Some(found);
+                    }
+                    first.get_or_insert(found);
+                }
             }
         }
-        // 未找到合适的区域
-        None
+        // 未找到合适的区域时都是 None
+        match self.policy {
+            PlacementPolicy::FirstFit => None,
+            PlacementPolicy::BestFit => best.map(|(_, found)| found),
+            // 上一次分配的位置之后没有合适的区域 -> 回到堆的开头
+            PlacementPolicy::NextFit => first,
+        }
     }
     /// 尝试将给定区域用于给定大小和对齐要求的分配。
     ///
     /// 成功时返回分配该内存区域的起始地址。
     fn alloc_from_region(region: &ListNode, size: usize, align: usize) -> Result<usize, ()> {
-        let alloc_start = align_up(region.start_addr(), align);
+        let mut alloc_start = align_up(region.start_addr(), align);
+        if alloc_start != region.start_addr()
+            && alloc_start - region.start_addr() < mem::size_of::<ListNode>()
+        {
+            // 对齐产生的前部空隙太小，放不下 ListNode，无法还回链表 -> 再往后对齐一次
+            alloc_start = align_up(region.start_addr() + mem::size_of::<ListNode>(), align);
+        }
         let alloc_end = alloc_start.checked_add(size).ok_or(())?;
 
         if alloc_end > region.end_addr() {
@@ -114,31 +311,100 @@ impl LinkListAllocator {
     }
 }
 
-unsafe impl GlobalAlloc for Locked<LinkListAllocator> {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let (size, align) = LinkListAllocator::size_align(layout);
-        let mut allocator = self.lock();
-        // 找到第一个合适的区域
-        if let Some((region, alloc_start)) = allocator.find_region(size, align) {
-            // 检查是否有足够的空间分配
-            let alloc_end = alloc_start.checked_add(size).expect("overflow");
-            // 区域剩余部分是否足够存储 ListNode 结构体
-            let excess_size = region.end_addr() - alloc_end;
+impl HeapStatistics for LinkListAllocator {
+    fn stats(&self) -> HeapStats {
+        let mut stats = self.counters.to_stats("linked list");
+        stats.heap_size = self.heap_end - self.heap_start;
+        let mut current = self.head.next.as_deref();
+        while let Some(region) = current {
+            stats.free_bytes += region.size;
+            stats.largest_free_region = stats.largest_free_region.max(region.size);
+            current = region.next.as_deref();
+        }
+        stats
+    }
+}
+
+impl LinkListAllocator {
+    /// 分配满足 `layout` 的内存，找不到合适的区域时扩展堆后再试一次
+    ///
+    /// 失败时返回空指针。固定大小块分配器也用它作为后备分配器
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Self::size_align(layout);
+        // 最坏情况下需要额外的对齐填充
+        let found = self.find_region(size, align).or_else(|| {
+            if self.grow(size + align) {
+                self.find_region(size, align)
+            } else {
+                None
+            }
+        });
+        let Some((region, alloc_start)) = found else {
+            return ptr::null_mut();
+        };
+        // 检查是否有足够的空间分配
+        let alloc_end = alloc_start.checked_add(size).expect("overflow");
+        // 区域剩余部分是否足够存储 ListNode 结构体
+        let excess_size = region.end_addr() - alloc_end;
+        // 对齐产生的前部空隙
+        let region_start = region.start_addr();
+        let front_size = alloc_start - region_start;
+        unsafe {
             if excess_size > 0 {
-                unsafe {
-                    // 区域剩余部分足够存储 ListNode 结构体
-                    // 将剩余部分添加到空闲链表中
-                    allocator.add_free_region(alloc_end, excess_size);
-                }
+                // 区域剩余部分足够存储 ListNode 结构体
+                // 将剩余部分添加到空闲链表中
+                self.add_free_region(alloc_end, excess_size);
+            }
+            if front_size > 0 {
+                // 前部空隙也还回链表，否则释放后堆无法合并回一整块
+                self.add_free_region(region_start, front_size);
             }
-            alloc_start as *mut u8
-        } else {
-            ptr::null_mut()
         }
+        self.counters.record_alloc(layout.size());
+        alloc_start as *mut u8
     }
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        let (size, _) = LinkListAllocator::size_align(layout);
+
+    /// 释放 `ptr` 处按 `layout` 分配的内存
+    ///
+    /// 这个函数是不安全的，因为调用者必须保证 `ptr` 是用同一个 `layout` 从这个分配器分配的
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Self::size_align(layout);
+        self.counters.record_free(layout.size());
         // 将已释放的内存区域添加到空闲链表中
-        unsafe { self.lock().add_free_region(ptr as usize, size) }
+        unsafe { self.add_free_region(ptr as usize, size) }
+    }
+
+    /// 尝试原地把 `ptr` 处按 `layout` 分配的内存调整为 `new_size` 字节，成功时返回 true
+    pub fn resize(&mut self, ptr: *mut u8, layout: Layout, new_size: usize) -> bool {
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        let (old_size, _) = Self::size_align(layout);
+        let (size, _) = Self::size_align(new_layout);
+        if !self.resize_in_place(ptr as usize, old_size, size) {
+            return false;
+        }
+        self.counters.record_resize(layout.size(), new_size);
+        true
+    }
+
+    /// 堆的起始位置
+    pub fn heap_start(&self) -> usize {
+        self.heap_start
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<LinkListAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.lock().allocate(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.lock().deallocate(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if self.lock().resize(ptr, layout, new_size) {
+            return ptr;
+        }
+        unsafe { super::realloc_by_copy(self, ptr, layout, new_size) }
     }
 }
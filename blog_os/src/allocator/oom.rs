@@ -0,0 +1,113 @@
+use crate::memory::accounting;
+use crate::serial_println;
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+/// 最多可以注册的回收函数数量
+pub const MAX_RECLAIMERS: usize = 8;
+
+/// 回收函数：尽量释放缓存的内存，返回释放的字节数
+///
+/// 它在分配失败时被调用，此时没有持有分配器的锁，但它自己不能再分配内存
+pub type ReclaimFn = fn() -> usize;
+
+/// 注册的回收函数已经达到 `MAX_RECLAIMERS` 个
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TooManyReclaimers;
+
+/// 一个注册的回收函数
+#[derive(Clone, Copy)]
+struct Reclaimer {
+    name: &'static str, // 打印诊断信息时使用的名字
+    reclaim: ReclaimFn,
+}
+
+static RECLAIMERS: Mutex<[Option<Reclaimer>; MAX_RECLAIMERS]> = Mutex::new([None; MAX_RECLAIMERS]);
+
+/// 正在执行回收函数，防止回收函数中的分配失败后再次进入回收
+static RECLAIMING: AtomicBool = AtomicBool::new(false);
+
+/// 注册一个在内存不足时调用的回收函数，例如把固定大小块分配器的空闲页还给后备分配器
+pub fn register_reclaimer(name: &'static str, reclaim: ReclaimFn) -> Result<(), TooManyReclaimers> {
+    let mut reclaimers = RECLAIMERS.lock();
+    let slot = reclaimers
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or(TooManyReclaimers)?;
+    *slot = Some(Reclaimer { name, reclaim });
+    Ok(())
+}
+
+/// 依次调用所有注册的回收函数，返回一共释放的字节数
+pub fn reclaim() -> usize {
+    if RECLAIMING.swap(true, Ordering::Acquire) {
+        return 0;
+    }
+    // 复制一份再调用，回收函数执行时不持有注册表的锁
+    let reclaimers = match RECLAIMERS.try_lock() {
+        Some(reclaimers) => *reclaimers,
+        None => [None; MAX_RECLAIMERS],
+    };
+    let mut total = 0;
+    for reclaimer in reclaimers.iter().flatten() {
+        let freed = (reclaimer.reclaim)();
+        if freed > 0 {
+            serial_println!("oom: reclaimer `{}` freed {} bytes", reclaimer.name, freed);
+        }
+        total += freed;
+    }
+    RECLAIMING.store(false, Ordering::Release);
+    total
+}
+
+/// 包装另一个分配器，分配失败时先调用回收函数再重试一次
+///
+/// 全局分配器总是被包装在它里面，重试之后仍然失败才会进入 `alloc_error_handler`
+pub struct ReclaimingAllocator<A: 'static> {
+    inner: &'static A,
+}
+
+impl<A> ReclaimingAllocator<A> {
+    pub const fn new(inner: &'static A) -> Self {
+        ReclaimingAllocator { inner }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for ReclaimingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+        if !ptr.is_null() || reclaim() == 0 {
+            return ptr;
+        }
+        unsafe { self.inner.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.inner.dealloc(ptr, layout) };
+    }
+
+    // 转发给内层分配器，让它有机会原地调整大小
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { self.inner.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() || reclaim() == 0 {
+            return new_ptr;
+        }
+        unsafe { self.inner.realloc(ptr, layout, new_size) }
+    }
+}
+
+/// 内存不足时的最后一步：打印失败的布局、堆和物理内存的使用情况，然后 panic
+///
+/// 由 `alloc_error_handler` 调用，这时回收函数已经执行过，分配仍然失败
+pub fn out_of_memory(layout: Layout) -> ! {
+    serial_println!(
+        "oom: failed to allocate {} bytes aligned to {}",
+        layout.size(),
+        layout.align()
+    );
+    // 统计信息中包含固定大小块分配器每个块大小的状态
+    super::report();
+    accounting::report();
+    panic!("out of memory: {:?}", layout)
+}
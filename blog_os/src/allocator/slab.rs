@@ -0,0 +1,273 @@
+use crate::memory::{
+    self,
+    accounting::{self, FrameOwner},
+};
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::{mem, ptr};
+use spin::Mutex;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame};
+
+/// 每个 slab 占用一个 4KiB 物理帧
+const SLAB_SIZE: usize = 4096;
+
+/// 空闲对象中存放的链表节点
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+/// 放在每个 slab 开头的头部，后面紧跟着对象
+///
+/// slab 通过物理内存映射访问，不占用内核虚拟地址空间。
+/// 因为 slab 按 4KiB 对齐，对象地址向下对齐就能找到它所属的 slab
+struct SlabHeader {
+    next: *mut SlabHeader, // 同一个缓存中的下一个 slab
+    free: *mut FreeObject, // slab 内的空闲对象链表
+    in_use: usize,         // 已经分配出去的对象数量
+    frame: PhysFrame,      // slab 所在的物理帧
+}
+
+/// 一个缓存的所有 slab
+struct SlabList {
+    head: *mut SlabHeader, // 所有 slab 组成的链表
+    slabs: usize,          // slab 数量
+    in_use: usize,         // 所有 slab 中已经分配出去的对象数量
+}
+
+// slab 链表只在持有缓存的锁时被访问
+unsafe impl Send for SlabList {}
+
+/// 一个缓存的使用情况
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlabStats {
+    pub name: &'static str,      // 缓存的名字
+    pub object_size: usize,      // 每个对象占用的字节数，包括对齐填充
+    pub objects_per_slab: usize, // 每个 slab 能放下的对象数量
+    pub slabs: usize,            // 当前持有的 slab 数量
+    pub active_objects: usize,   // 已经分配出去的对象数量
+    pub total_objects: usize,    // 所有 slab 能放下的对象总数
+}
+
+/// 存放 `T` 类型对象的 slab 缓存
+///
+/// 同一种对象集中放在按页分配的 slab 中，不会和通用堆上的其他分配交错，
+/// 适合频繁创建和销毁的内核对象。通常声明为 `static`
+pub struct SlabCache<T> {
+    name: &'static str,
+    ctor: Option<fn() -> T>, // 可选的构造函数，用于 `alloc_constructed`
+    slabs: Mutex<SlabList>,
+    _marker: PhantomData<fn() -> T>, // 缓存本身不持有 `T`，对象只能通过 `SlabBox` 访问
+}
+
+impl<T> SlabCache<T> {
+    /// 对象的对齐要求，至少要能放下空闲链表节点
+    const ALIGN: usize = max(mem::align_of::<T>(), mem::align_of::<FreeObject>());
+    /// 每个对象占用的字节数
+    const OBJECT_SIZE: usize = align_up(
+        max(mem::size_of::<T>(), mem::size_of::<FreeObject>()),
+        Self::ALIGN,
+    );
+    /// 第一个对象相对于 slab 开头的偏移
+    const FIRST_OBJECT: usize = align_up(mem::size_of::<SlabHeader>(), Self::ALIGN);
+    /// 每个 slab 能放下的对象数量
+    const OBJECTS_PER_SLAB: usize = (SLAB_SIZE - Self::FIRST_OBJECT) / Self::OBJECT_SIZE;
+
+    /// 创建一个没有构造函数的空缓存
+    pub const fn new(name: &'static str) -> Self {
+        assert!(Self::OBJECTS_PER_SLAB > 0, "object is too large for a slab");
+        SlabCache {
+            name,
+            ctor: None,
+            slabs: Mutex::new(SlabList {
+                head: ptr::null_mut(),
+                slabs: 0,
+                in_use: 0,
+            }),
+            _marker: PhantomData,
+        }
+    }
+
+    /// 创建一个带构造函数的空缓存
+    pub const fn with_constructor(name: &'static str, ctor: fn() -> T) -> Self {
+        let mut cache = Self::new(name);
+        cache.ctor = Some(ctor);
+        cache
+    }
+
+    /// 缓存的名字
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// 从缓存中分配一个对象并放入 `value`，物理内存耗尽时返回 None
+    pub fn alloc(&self, value: T) -> Option<SlabBox<'_, T>> {
+        let object = self.alloc_raw()?;
+        unsafe { object.write(value) };
+        Some(SlabBox {
+            cache: self,
+            object,
+        })
+    }
+
+    /// 用缓存的构造函数创建一个对象
+    ///
+    /// 缓存没有构造函数时会 panic
+    pub fn alloc_constructed(&self) -> Option<SlabBox<'_, T>> {
+        let ctor = self
+            .ctor
+            .unwrap_or_else(|| panic!("slab cache {} has no constructor", self.name));
+        self.alloc(ctor())
+    }
+
+    /// 返回缓存当前的使用情况
+    pub fn stats(&self) -> SlabStats {
+        let slabs = self.slabs.lock();
+        SlabStats {
+            name: self.name,
+            object_size: Self::OBJECT_SIZE,
+            objects_per_slab: Self::OBJECTS_PER_SLAB,
+            slabs: slabs.slabs,
+            active_objects: slabs.in_use,
+            total_objects: slabs.slabs * Self::OBJECTS_PER_SLAB,
+        }
+    }
+
+    /// 把所有完全空闲的 slab 还给帧分配器，返回释放的帧数
+    pub fn shrink(&self) -> usize {
+        let mut slabs = self.slabs.lock();
+        let mut released = 0;
+        let mut link: *mut *mut SlabHeader = &mut slabs.head;
+        unsafe {
+            while !(*link).is_null() {
+                let slab = *link;
+                if (*slab).in_use == 0 {
+                    *link = (*slab).next; // 从链表中摘下
+                    let frame = (*slab).frame;
+                    memory::with_kernel_memory(|_, frame_allocator| {
+                        frame_allocator.deallocate_frame(frame)
+                    });
+                    accounting::uncharge(FrameOwner::Slab, 1);
+                    released += 1;
+                } else {
+                    link = &mut (*slab).next;
+                }
+            }
+        }
+        slabs.slabs -= released;
+        released
+    }
+
+    /// 分配一块能放下 `T` 的未初始化内存
+    fn alloc_raw(&self) -> Option<*mut T> {
+        let mut slabs = self.slabs.lock();
+        let mut slab = slabs.head;
+        // 找到第一个还有空闲对象的 slab
+        while !slab.is_null() && unsafe { (*slab).free.is_null() } {
+            slab = unsafe { (*slab).next };
+        }
+        if slab.is_null() {
+            slab = Self::new_slab()?;
+            unsafe { (*slab).next = slabs.head };
+            slabs.head = slab;
+            slabs.slabs += 1;
+        }
+        slabs.in_use += 1;
+        unsafe {
+            let object = (*slab).free;
+            (*slab).free = (*object).next;
+            (*slab).in_use += 1;
+            Some(object as *mut T)
+        }
+    }
+
+    /// 把 `object` 还给它所属的 slab
+    ///
+    /// 这个函数是不安全的，因为 `object` 必须是这个缓存分配的，并且已经被析构
+    unsafe fn free_raw(&self, object: *mut T) {
+        let mut slabs = self.slabs.lock();
+        let slab = (object as usize & !(SLAB_SIZE - 1)) as *mut SlabHeader;
+        let object = object as *mut FreeObject;
+        unsafe {
+            (*object).next = (*slab).free;
+            (*slab).free = object;
+            (*slab).in_use -= 1;
+        }
+        slabs.in_use -= 1;
+    }
+
+    /// 分配一个物理帧，在其中建立 slab 头部和空闲对象链表
+    fn new_slab() -> Option<*mut SlabHeader> {
+        let (frame, phys_offset) = memory::with_kernel_memory(|mapper, frame_allocator| {
+            Some((frame_allocator.allocate_frame()?, mapper.phys_offset()))
+        })
+        .expect("memory::install must be called before using slab caches")?;
+        accounting::charge(FrameOwner::Slab, 1);
+        let start = phys_offset + frame.start_address().as_u64();
+        let slab = start.as_mut_ptr::<SlabHeader>();
+        // 从后往前把所有对象串成链表，这样分配时按地址从小到大
+        let mut free = ptr::null_mut();
+        for index in (0..Self::OBJECTS_PER_SLAB).rev() {
+            let offset = Self::FIRST_OBJECT + index * Self::OBJECT_SIZE;
+            let object = (start + offset as u64).as_mut_ptr::<FreeObject>();
+            unsafe { object.write(FreeObject { next: free }) };
+            free = object;
+        }
+        unsafe {
+            slab.write(SlabHeader {
+                next: ptr::null_mut(),
+                free,
+                in_use: 0,
+                frame,
+            })
+        };
+        Some(slab)
+    }
+}
+
+impl<T> Drop for SlabCache<T> {
+    /// `SlabBox` 借用了缓存，所以缓存被丢弃时所有 slab 都是空闲的
+    fn drop(&mut self) {
+        self.shrink();
+    }
+}
+
+/// 指向 slab 缓存中一个对象的智能指针，被丢弃时析构对象并把内存还给缓存
+pub struct SlabBox<'a, T> {
+    cache: &'a SlabCache<T>,
+    object: *mut T,
+}
+
+impl<T> Deref for SlabBox<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.object }
+    }
+}
+
+impl<T> DerefMut for SlabBox<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.object }
+    }
+}
+
+impl<T> Drop for SlabBox<'_, T> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.object);
+            self.cache.free_raw(self.object);
+        }
+    }
+}
+
+unsafe impl<T: Send> Send for SlabBox<'_, T> {}
+unsafe impl<T: Sync> Sync for SlabBox<'_, T> {}
+
+const fn max(a: usize, b: usize) -> usize {
+    if a > b { a } else { b }
+}
+
+/// `super::align_up` 的 const 版本
+const fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
@@ -0,0 +1,165 @@
+use super::fixed_size_block::BLOCK_SIZES;
+use core::fmt;
+
+/// 堆分配器的统计信息，所有分配器都用这个结构报告自己的状态
+///
+/// 字节数按分配时请求的 `Layout::size` 计算，不包括对齐填充和分配器内部的开销。
+/// 后备分配器和块大小只有固定大小块分配器才有，其他分配器的这些计数都是 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    pub allocator: &'static str,                           // 分配器的名字
+    pub heap_size: usize,                                  // 堆当前已经映射的字节数
+    pub bytes_in_use: usize,                               // 已经分配出去的字节数
+    pub peak_bytes_in_use: usize,                          // 已分配字节数的峰值
+    pub allocations: usize,                                // 成功的分配次数
+    pub frees: usize,                                      // 释放次数
+    pub free_bytes: usize,                                 // 所有空闲区域的总字节数
+    pub largest_free_region: usize,                        // 不扩容时能满足的最大分配
+    pub fallback_allocations: usize,                       // 直接由后备分配器满足的分配次数
+    pub fallback_bytes_in_use: usize,                      // 后备堆中已经分配出去的字节数
+    pub size_classes: [SizeClassStats; BLOCK_SIZES.len()], // 每个块大小的使用情况
+}
+
+/// 固定大小块分配器中一个块大小的使用情况，其他分配器的计数都是 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeClassStats {
+    pub block_size: usize,  // 块大小
+    pub hits: usize,        // 直接从空闲链表取到块的分配次数
+    pub refills: usize,     // 空闲链表为空，切分一整页补充链表的次数
+    pub pages: usize,       // 当前切成这种块的页数
+    pub free_blocks: usize, // 空闲链表中的块数
+}
+
+impl SizeClassStats {
+    /// 所有块大小，计数都为 0
+    pub(super) fn empty() -> [SizeClassStats; BLOCK_SIZES.len()] {
+        let mut classes = [SizeClassStats {
+            block_size: 0,
+            hits: 0,
+            refills: 0,
+            pages: 0,
+            free_blocks: 0,
+        }; BLOCK_SIZES.len()];
+        for (class, &block_size) in classes.iter_mut().zip(BLOCK_SIZES) {
+            class.block_size = block_size;
+        }
+        classes
+    }
+}
+
+impl HeapStats {
+    /// 外部碎片率，单位是百分比
+    ///
+    /// 定义为不在最大空闲区域中的空闲字节所占的比例：0 表示所有空闲内存连成一块，
+    /// 接近 100 表示空闲内存分散成许多小块，总量够用也满足不了大的分配
+    pub fn fragmentation(&self) -> usize {
+        if self.free_bytes == 0 {
+            return 0;
+        }
+        let largest = self.largest_free_region.min(self.free_bytes);
+        (self.free_bytes - largest) * 100 / self.free_bytes
+    }
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} heap: size {} KiB, in use {} bytes (peak {}), free {} bytes",
+            self.allocator,
+            self.heap_size / 1024,
+            self.bytes_in_use,
+            self.peak_bytes_in_use,
+            self.free_bytes
+        )?;
+        writeln!(
+            f,
+            "largest free region {} bytes, fragmentation {}%",
+            self.largest_free_region,
+            self.fragmentation()
+        )?;
+        write!(
+            f,
+            "allocations {}, frees {}, fallback allocations {}, fallback in use {} bytes",
+            self.allocations, self.frees, self.fallback_allocations, self.fallback_bytes_in_use
+        )?;
+        for class in self.size_classes.iter() {
+            if class.hits + class.refills + class.free_blocks == 0 {
+                continue; // 没有用过的块大小不打印
+            }
+            write!(
+                f,
+                "\n  {:>4} bytes: hits {}, refills {}, pages {}, free blocks {}",
+                class.block_size, class.hits, class.refills, class.pages, class.free_blocks
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// 报告统计信息的堆分配器
+pub trait HeapStatistics {
+    /// 返回分配器当前的统计信息
+    ///
+    /// 只读取分配器的状态，不会为了统计去分配或释放内存，所以也可以在内存不足时调用
+    fn stats(&self) -> HeapStats;
+}
+
+/// 所有分配器共用的分配计数
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Counters {
+    pub bytes_in_use: usize,
+    pub peak_bytes_in_use: usize,
+    pub allocations: usize,
+    pub frees: usize,
+}
+
+impl Counters {
+    pub const fn new() -> Self {
+        Counters {
+            bytes_in_use: 0,
+            peak_bytes_in_use: 0,
+            allocations: 0,
+            frees: 0,
+        }
+    }
+
+    /// 记录一次成功的分配
+    pub fn record_alloc(&mut self, size: usize) {
+        self.allocations += 1;
+        self.bytes_in_use += size;
+        self.peak_bytes_in_use = self.peak_bytes_in_use.max(self.bytes_in_use);
+    }
+
+    /// 记录一次释放
+    ///
+    /// 释放时给出的 `Layout` 可能和分配时不一致 (堆调试模式会报告这种错误)，
+    /// 所以和帧的记账一样，计数不会减到 0 以下
+    pub fn record_free(&mut self, size: usize) {
+        self.frees += 1;
+        self.bytes_in_use = self.bytes_in_use.saturating_sub(size);
+    }
+
+    /// 记录一次原地调整大小，分配和释放的次数不变
+    pub fn record_resize(&mut self, old_size: usize, new_size: usize) {
+        self.bytes_in_use = self.bytes_in_use.saturating_sub(old_size) + new_size;
+        self.peak_bytes_in_use = self.peak_bytes_in_use.max(self.bytes_in_use);
+    }
+
+    /// 用这些计数填写一份统计信息，其余字段由分配器自己填写
+    pub fn to_stats(self, allocator: &'static str) -> HeapStats {
+        HeapStats {
+            allocator,
+            heap_size: 0,
+            bytes_in_use: self.bytes_in_use,
+            peak_bytes_in_use: self.peak_bytes_in_use,
+            allocations: self.allocations,
+            frees: self.frees,
+            free_bytes: 0,
+            largest_free_region: 0,
+            fallback_allocations: 0,
+            fallback_bytes_in_use: 0,
+            size_classes: SizeClassStats::empty(),
+        }
+    }
+}
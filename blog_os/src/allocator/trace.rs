@@ -0,0 +1,202 @@
+use crate::memory::stack;
+use crate::serial_println;
+use crate::task;
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::arch::asm;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use x86_64::VirtAddr;
+
+/// 每条记录最多保存的返回地址数量
+pub const MAX_CALLERS: usize = 4;
+
+/// 是否输出记录
+static ENABLED: AtomicBool = AtomicBool::new(true);
+/// 下一条记录的序号
+static NEXT_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// 开始输出记录
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// 停止输出记录，例如在打印大量信息之前避免刷屏
+pub fn disable() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+/// 已经输出的记录数量
+pub fn records() -> u64 {
+    NEXT_SEQ.load(Ordering::Relaxed)
+}
+
+/// 记录的类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceKind {
+    Alloc,
+    Free,
+}
+
+/// 一条分配或释放记录
+///
+/// 输出到串口时是一行用空格分隔的字段，数字都是不带前缀的十六进制：
+///
+/// ```text
+/// @heap <a|f> <序号> <指针> <大小> <对齐> <任务 ID 或 -> <返回地址,返回地址,...>
+/// ```
+///
+/// `a` 表示分配，`f` 表示释放，`realloc` 记录为旧指针的释放和新指针的分配。
+/// 序号从 0 开始连续递增，中间缺号说明有记录丢失。返回地址从分配器的调用者开始
+/// 沿帧指针向上回溯，可以用 addr2line 转换成源码位置。主机上的脚本只需要过滤
+/// 以 `@heap ` 开头的行，没有对应释放记录的分配就是泄漏
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub kind: TraceKind,
+    pub seq: u64,                      // 记录的序号
+    pub ptr: usize,                    // 分配或释放的指针
+    pub layout: Layout,                // 调用者给出的布局
+    pub task: Option<u64>,             // 正在运行的任务 ID
+    pub callers: [usize; MAX_CALLERS], // 返回地址，从最近的调用者开始
+    pub depth: usize,                  // `callers` 中有效的数量
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            TraceKind::Alloc => 'a',
+            TraceKind::Free => 'f',
+        };
+        write!(
+            f,
+            "@heap {} {:x} {:x} {:x} {:x} ",
+            kind,
+            self.seq,
+            self.ptr,
+            self.layout.size(),
+            self.layout.align()
+        )?;
+        match self.task {
+            Some(task) => write!(f, "{:x} ", task)?,
+            None => write!(f, "- ")?,
+        }
+        if self.depth == 0 {
+            return write!(f, "-");
+        }
+        for (index, caller) in self.callers[..self.depth].iter().enumerate() {
+            if index > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{:x}", caller)?;
+        }
+        Ok(())
+    }
+}
+
+/// 沿帧指针链回溯，返回最多 `MAX_CALLERS` 个返回地址
+///
+/// 只有用 `-C force-frame-pointers=yes` 编译时才有意义，目标配置默认不保留帧指针，
+/// 这时 rbp 可能是任意值。所以只在 `memory::stack` 分配的内核栈上回溯，每一帧都必须
+/// 完整地位于这个栈中并且在上一帧之上，不会读取栈以外的内存。在其他栈上
+/// (例如 bootloader 建立的启动栈) 无法确定栈的范围，不记录返回地址
+#[inline(always)]
+fn callers() -> ([usize; MAX_CALLERS], usize) {
+    let mut callers = [0; MAX_CALLERS];
+    let mut depth = 0;
+    let mut frame: usize;
+    let mut lower: usize; // 下一帧至少要在这个地址之上
+    unsafe {
+        asm!(
+            "mov {}, rbp",
+            "mov {}, rsp",
+            out(reg) frame,
+            out(reg) lower,
+            options(nomem, nostack, preserves_flags)
+        )
+    };
+    let Some(current) = stack::containing(VirtAddr::new(lower as u64)) else {
+        return (callers, 0);
+    };
+    let top = current.top().as_u64() as usize;
+    while depth < MAX_CALLERS && frame.is_multiple_of(8) && frame >= lower && frame <= top - 16 {
+        // 帧的布局：[rbp] 是上一帧的 rbp，[rbp + 8] 是返回地址
+        let (next, return_address) = unsafe {
+            let frame = frame as *const usize;
+            (frame.read(), frame.add(1).read())
+        };
+        if return_address == 0 {
+            break;
+        }
+        callers[depth] = return_address;
+        depth += 1;
+        lower = frame + 16;
+        frame = next;
+    }
+    (callers, depth)
+}
+
+/// 生成一条记录并输出到串口
+#[inline(always)]
+fn emit(kind: TraceKind, ptr: *mut u8, layout: Layout) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let (callers, depth) = callers();
+    let record = TraceRecord {
+        kind,
+        seq: NEXT_SEQ.fetch_add(1, Ordering::Relaxed),
+        ptr: ptr as usize,
+        layout,
+        task: task::current_task_id(),
+        callers,
+        depth,
+    };
+    // 串口输出不会分配内存，所以不会递归进入分配器
+    serial_println!("{}", record);
+}
+
+/// 包装另一个分配器，记录经过它的每次分配和释放
+///
+/// 启用 `heap-trace` feature 后全局分配器会被包装在它里面
+pub struct TracingAllocator<A: 'static> {
+    inner: &'static A,
+}
+
+impl<A> TracingAllocator<A> {
+    pub const fn new(inner: &'static A) -> Self {
+        TracingAllocator { inner }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TracingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+        if !ptr.is_null() {
+            emit(TraceKind::Alloc, ptr, layout);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            emit(TraceKind::Alloc, ptr, layout);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        emit(TraceKind::Free, ptr, layout);
+        unsafe { self.inner.dealloc(ptr, layout) };
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { self.inner.realloc(ptr, layout, new_size) };
+        // 失败时原来的内存保持不变，不输出记录
+        if !new_ptr.is_null() {
+            let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+            emit(TraceKind::Free, ptr, layout);
+            emit(TraceKind::Alloc, new_ptr, new_layout);
+        }
+        new_ptr
+    }
+}
@@ -1,3 +1,5 @@
+use crate::memory::{self, stack::KernelStack};
+use conquer_once::spin::OnceCell;
 use lazy_static::lazy_static;
 use x86_64::VirtAddr;
 
@@ -10,7 +12,9 @@ lazy_static! {
     static ref TSS: TaskStateSegment = {
         let mut tss = TaskStateSegment::new(); // 创建一个新的 TSS
         tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5; // 栈内存大小为 5 页大小的原始内存数组
+            // 启动早期的后备栈，没有保护页，只在 `memory::install` 之前发生 double fault 时使用。
+            // 之后 `init_stacks` 会把它换成带保护页的栈，所以只需要够处理函数打印错误信息
+            const STACK_SIZE: usize = 4096 * 2; // 栈内存大小为 2 页大小的原始内存数组
             // 一定要 static mut 而不是 static
             // 要把这个 STACK 放到可写的内存段中
             // 否则 bootloader 会将其分配到只读页中
@@ -26,27 +30,81 @@ lazy_static! {
 
 // 全局描述符表 GDT
 lazy_static! {
-    static ref GDT: (GlobalDescriptorTable, Selectors) = {
-        let mut gdt = GlobalDescriptorTable::new(); // 注册一个全局描述符表 GDT
-        let code_selector = gdt.add_entry(Descriptor::kernel_code_segment()); // 添加内核代码段描述符
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS)); // 添加 TSS 段描述符
-        (gdt, Selectors {code_selector,tss_selector}) // 返回 GDT 和 选择子
-    };
+    static ref GDT: (GlobalDescriptorTable, Selectors) = build_gdt(&TSS);
 }
 
+/// 为 IST 分配的带保护页的栈大小 (页)
+const IST_STACK_PAGES: u64 = 5;
+
+// 使用带保护页的栈的 TSS 和 GDT，在 `init_stacks` 之后取代上面启动时的版本
+static GUARDED_TSS: OnceCell<TaskStateSegment> = OnceCell::uninit();
+static GUARDED_GDT: OnceCell<(GlobalDescriptorTable, Selectors)> = OnceCell::uninit();
+static DOUBLE_FAULT_STACK: OnceCell<KernelStack> = OnceCell::uninit();
+
 // 段选择子结构体
 struct Selectors {
     code_selector: SegmentSelector, // 代码段选择子
     tss_selector: SegmentSelector,  // TSS 段选择子
 }
 
-pub fn init() {
+// 创建包含内核代码段和给定 TSS 的 GDT
+fn build_gdt(tss: &'static TaskStateSegment) -> (GlobalDescriptorTable, Selectors) {
+    let mut gdt = GlobalDescriptorTable::new(); // 注册一个全局描述符表 GDT
+    let code_selector = gdt.add_entry(Descriptor::kernel_code_segment()); // 添加内核代码段描述符
+    let tss_selector = gdt.add_entry(Descriptor::tss_segment(tss)); // 添加 TSS 段描述符
+    (
+        gdt,
+        Selectors {
+            code_selector,
+            tss_selector,
+        },
+    ) // 返回 GDT 和 选择子
+}
+
+// 加载 GDT，并更新代码段寄存器和 TSS
+fn load(gdt: &'static (GlobalDescriptorTable, Selectors)) {
     use x86_64::instructions::segmentation::{CS, Segment};
     use x86_64::instructions::tables::load_tss;
 
-    GDT.0.load(); // 加载 GDT 表
+    gdt.0.load(); // 加载 GDT 表
     unsafe {
-        CS::set_reg(GDT.1.code_selector); // 更新代码段寄存器
-        load_tss(GDT.1.tss_selector); // 加载 TSS 段选择子
+        CS::set_reg(gdt.1.code_selector); // 更新代码段寄存器
+        load_tss(gdt.1.tss_selector); // 加载 TSS 段选择子
     }
 }
+
+/// 加载启动时的 GDT 和 TSS
+///
+/// 这时 double fault 使用的还是上面的静态后备栈，`memory::install` 会调用 `init_stacks`
+/// 换成带保护页的栈。没有初始化内存管理的程序 (例如不需要分页的集成测试) 会一直使用后备栈
+pub fn init() {
+    load(&GDT);
+}
+
+/// 为所有 IST 分配带保护页的栈，并切换到使用这些栈的 GDT 和 TSS
+///
+/// 启动时的 double fault 栈是一个静态数组，下面没有保护页，溢出时会悄悄破坏相邻的静态变量。
+/// 由 `memory::install` 在全局映射器和帧分配器可用之后调用，只能调用一次。
+/// 内核启动时运行的栈由 bootloader 建立，不在这里替换，它的保护页不归这里管理
+pub fn init_stacks() {
+    // 在闭包里分配，重复调用时不会先分配一个栈再泄漏掉
+    DOUBLE_FAULT_STACK
+        .try_init_once(|| {
+            memory::stack::alloc_stack(IST_STACK_PAGES).expect("failed to allocate IST stack")
+        })
+        .expect("gdt::init_stacks should only be called once");
+    let stack = double_fault_stack().unwrap();
+    GUARDED_TSS.init_once(|| {
+        let mut tss = TaskStateSegment::new();
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = stack.top();
+        tss
+    });
+    // 加载新的 GDT 后，新的 TSS 描述符不是忙状态，可以直接加载
+    GUARDED_GDT.init_once(|| build_gdt(GUARDED_TSS.get().unwrap()));
+    load(GUARDED_GDT.get().unwrap());
+}
+
+/// 返回 double fault 使用的带保护页的栈，在 `init_stacks` 之前为 None
+pub fn double_fault_stack() -> Option<KernelStack> {
+    DOUBLE_FAULT_STACK.get().copied()
+}
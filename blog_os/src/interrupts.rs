@@ -1,4 +1,5 @@
 use crate::gdt;
+use crate::memory;
 use crate::{print, println};
 use lazy_static::lazy_static;
 use pic8259::ChainedPics; // 用于映射主副 PIC 的映射布局
@@ -67,6 +68,14 @@ extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
     _err_code: u64,
 ) -> ! {
+    use x86_64::registers::control::Cr2;
+    // 栈溢出时 CPU 无法在栈上压入页错误的异常帧，于是升级为 double fault
+    if let Some(stack) = memory::stack::guard_page_hit(Cr2::read()) {
+        panic!(
+            "EXCEPTION: DOUBLE FAULT (kernel stack overflow)\n{:#?}\n{:#?}",
+            stack, stack_frame
+        );
+    }
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
@@ -106,8 +115,17 @@ extern "x86-interrupt" fn page_fault_handler(
     // CR2寄存器是会在 page fault 发生时，被 CPU 自动写入导致异常的虚拟地址
     use x86_64::registers::control::Cr2;
 
+    let addr = Cr2::read(); // 可以用 read 函数读取该寄存器
+    // 如果是按需分配区域里的缺页，映射好新的帧后直接返回，CPU 会重新执行触发错误的指令
+    if memory::fault::handle_page_fault(addr, err_code) {
+        return;
+    }
+
     println!("EXCEPTION: PAGE FAULT");
-    println!("Accessed Address: {:?}", Cr2::read()); // 可以用 read 函数读取并打印该寄存器
+    println!("Accessed Address: {:?}", addr);
+    if let Some(stack) = memory::stack::guard_page_hit(addr) {
+        println!("Kernel stack overflow: {:?}", stack); // 访问了内核栈的保护页
+    }
     println!("Error Code: {:?}", err_code);
     println!("{:#?}", stack_frame);
     hlt_loop();
@@ -4,6 +4,7 @@
 #![test_runner(crate::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 #![feature(abi_x86_interrupt)] // x86-interrupt 并不是稳定特性，需要手动启用
+#![feature(alloc_error_handler)]
 pub mod allocator;
 pub mod gdt;
 pub mod interrupts;
@@ -34,6 +35,9 @@ pub fn exit_qemu(exit_code: QemuExitCode) {
 //      EXIT QEMU END
 // ==================
 
+/// 初始化 GDT、IDT 和 PIC，并启用中断
+///
+/// IST 先使用静态的后备栈，`memory::install` 之后才切换到带保护页的栈，见 `gdt::init`
 pub fn init() {
     gdt::init(); // gdt: 定义 CPU 如何执行程序 (段、权限、TSS)
     interrupts::init_idt(); // idt: 定义 CPU 遇到事件后该跳去哪 (中断与异常处理函数)
@@ -49,6 +53,12 @@ pub fn hlt_loop() -> ! {
     }
 }
 
+// 回收之后分配仍然失败时调用，打印诊断信息后 panic
+#[alloc_error_handler]
+fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
+    allocator::oom::out_of_memory(layout)
+}
+
 // ==================
 //      TASTABLE
 pub trait Testable {
@@ -38,7 +38,7 @@ async fn example_task() {
 
 fn kernel_main(boot_info: &'static BootInfo) -> ! {
     use blog_os::allocator;
-    use blog_os::memory::{self, BootInfoFrameAllocator};
+    use blog_os::memory::{self, bitmap::BitmapFrameAllocator};
     use x86_64::VirtAddr;
 
     println!("Hello World{}\n", "!");
@@ -48,12 +48,18 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     test_main();
 
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset); // 物理内存偏移量
+    // 把启动时的物理内存布局打印到串口
+    memory::accounting::report_memory_map(&boot_info.memory_map);
     // 初始化页表映射器，用于后续的内存映射操作
-    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mapper = unsafe { memory::init(phys_mem_offset) };
     // 初始化帧分配器，用于后续的物理内存分配操作
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    // 交给内核全局使用，堆扩容时需要在分配器内部映射新的页面
+    // 同时把启动时的 double fault 栈换成带保护页的栈
+    memory::install(mapper, frame_allocator);
 
-    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap init failed");
+    allocator::init_heap().expect("heap init failed");
 
     let mut executor = Executor::new();
     executor.spawn(Task::new(example_task()));
@@ -1,21 +1,109 @@
+use bitmap::BitmapFrameAllocator;
+use buddy::BuddyFrameAllocator;
+use spin::Mutex;
+use x86_64::registers::control::{Cr0, Cr0Flags};
 use x86_64::structures::paging::{
-    FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PhysFrame, Size4KiB,
+    FrameAllocator, Mapper, OffsetPageTable, Page, PageSize, PageTable, PageTableFlags, PhysFrame,
+    Size4KiB,
 };
 use x86_64::{PhysAddr, VirtAddr};
+pub mod accounting;
+pub mod address_space;
+pub mod bitmap;
+pub mod buddy;
+pub mod cow;
+pub mod dma;
+pub mod dump;
+pub mod fault;
+pub mod mmio;
+pub mod stack;
+pub mod vma;
+pub mod vmalloc;
+pub mod wx;
 
 /// 初始化一个新的OffsetPageTable。
 ///
 /// 这个函数是不安全的
 /// 因为调用者必须保证完整的物理内存能在传递的 `physical_memory_offset` 被映射到虚拟内存
 /// 必须保证只被调用一次，以避免 &mut 引用的别名问题
+///
+/// 同时打开 NXE 并给所有可写页面加上 NO_EXECUTE，之后没有页面既可写又可执行
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
-    unsafe {
+    let mut mapper = unsafe {
+        // 让内核写入只读页面时也触发页错误，写时复制依赖于这一点
+        Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT));
         let level_4_table = active_level_4_table(physical_memory_offset);
         // 创建一个新的 OffsetPageTable 实例
         // 用于将虚拟地址转换为物理地址
-        // 并返回一个新的 OffsetPageTable 实例
         OffsetPageTable::new(level_4_table, physical_memory_offset)
+    };
+    wx::enable_nxe();
+    wx::enforce(&mut mapper);
+    mapper
+}
+
+/// 内核全局的页表映射器，在 `install` 之后可用
+pub static MAPPER: Mutex<Option<OffsetPageTable<'static>>> = Mutex::new(None);
+/// 内核全局的帧分配器，在 `install` 之后可用
+pub static FRAME_ALLOCATOR: Mutex<Option<BitmapFrameAllocator>> = Mutex::new(None);
+
+/// 连续物理内存池，由伙伴分配器管理，在 `install` 之后可用
+///
+/// DMA 缓冲区和 2MiB 大页从这里分配，其他的帧都来自 `FRAME_ALLOCATOR`
+pub static CONTIGUOUS_FRAMES: Mutex<Option<BuddyFrameAllocator>> = Mutex::new(None);
+
+/// 连续物理内存池的大小 (帧)，正好是伙伴分配器最大的一块 (4 MiB)
+pub const CONTIGUOUS_POOL_FRAMES: usize = 1 << buddy::MAX_ORDER;
+
+/// 把映射器和帧分配器交给内核全局使用
+///
+/// 堆扩容等需要在分配器内部修改页表的功能依赖于此。同时从帧分配器中取出一块
+/// 按 4 MiB 对齐的连续内存交给伙伴分配器，尽量放在 ISA DMA 能访问的 16 MiB 以下，
+/// 最后通过 `gdt::init_stacks` 让所有 IST 使用带保护页的栈，所以只能调用一次
+pub fn install(mapper: OffsetPageTable<'static>, mut frame_allocator: BitmapFrameAllocator) {
+    let pool_size = CONTIGUOUS_POOL_FRAMES as u64 * Size4KiB::SIZE;
+    let pool = [dma::ISA_DMA_LIMIT, dma::DMA32_LIMIT]
+        .into_iter()
+        .find_map(|limit| {
+            frame_allocator.allocate_contiguous(CONTIGUOUS_POOL_FRAMES, pool_size, limit)
+        });
+    let mut contiguous = BuddyFrameAllocator::empty(mapper.phys_offset());
+    if let Some(first) = pool {
+        let start = first.start_address();
+        // 这些帧已经从位图中取出，只属于伙伴分配器
+        unsafe { contiguous.add_region(start, start + pool_size) };
     }
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        *MAPPER.lock() = Some(mapper);
+        *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+        *CONTIGUOUS_FRAMES.lock() = Some(contiguous);
+    });
+    // 从这里开始可以分配带保护页的栈，把启动时的 IST 栈换掉
+    crate::gdt::init_stacks();
+}
+
+/// 在关闭中断的情况下锁住连续物理内存池，并把它交给 `f`
+///
+/// 不会同时锁住 `MAPPER` 或 `FRAME_ALLOCATOR`。如果还没有调用 `install`，返回 None
+pub fn with_contiguous_frames<R>(f: impl FnOnce(&mut BuddyFrameAllocator) -> R) -> Option<R> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        Some(f(CONTIGUOUS_FRAMES.lock().as_mut()?))
+    })
+}
+
+/// 在关闭中断的情况下锁住全局映射器和帧分配器，并把它们交给 `f`
+///
+/// 总是先锁 `MAPPER` 再锁 `FRAME_ALLOCATOR`，以避免死锁。
+/// 堆扩容时会在持有堆锁的情况下调用此函数，所以 `f` 中不能进行堆分配。
+/// 如果还没有调用 `install`，返回 None
+pub fn with_kernel_memory<R>(
+    f: impl FnOnce(&mut OffsetPageTable<'static>, &mut BitmapFrameAllocator) -> R,
+) -> Option<R> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut mapper = MAPPER.lock();
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        Some(f(mapper.as_mut()?, frame_allocator.as_mut()?))
+    })
 }
 
 /// 返回一个对活动的4级页表的可变引用
@@ -47,8 +135,8 @@ pub fn create_example_mapping(
     use x86_64::structures::paging::PageTableFlags as Flags;
     // 要映射的物理框架
     let frame = PhysFrame::containing_address(PhysAddr::new(0xb8000));
-    // 映射标志，这里设置为存在和可写
-    let flags = Flags::PRESENT | Flags::WRITABLE;
+    // 映射标志，这里设置为存在、可写和不可执行
+    let flags = Flags::PRESENT | Flags::WRITABLE | Flags::NO_EXECUTE;
     // 执行映射操作，将虚拟页面映射到物理框架，让 page -> frame
     let map_to_result = unsafe { mapper.map_to(page, frame, flags, frame_allocator) };
     // 检查映射是否成功，失败则 panic
@@ -103,20 +191,54 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     }
 }
 
-//////////////////////////////////////////////////////////////////// 可删 👇
+/// 页面映射的大小
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MappingSize {
+    Size4KiB, // 普通页，由 1 级页表项映射
+    Size2MiB, // 大页，由设置了 HUGE_PAGE 的 2 级页表项映射
+    Size1GiB, // 巨页，由设置了 HUGE_PAGE 的 3 级页表项映射
+}
+
+impl MappingSize {
+    /// 返回映射大小对应的字节数
+    pub fn bytes(self) -> u64 {
+        match self {
+            MappingSize::Size4KiB => 4096,
+            MappingSize::Size2MiB => 2 * 1024 * 1024,
+            MappingSize::Size1GiB => 1024 * 1024 * 1024,
+        }
+    }
+}
+
+/// 一次地址翻译的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Translation {
+    pub phys_addr: PhysAddr,   // 虚拟地址对应的物理地址
+    pub size: MappingSize,     // 包含该地址的映射的大小
+    pub flags: PageTableFlags, // 最终映射该地址的页表项的标志位
+}
+
 /// 将给定的虚拟地址转换为映射的物理地址，如果地址没有被映射，则为`None'。
 ///
 /// 这个函数是不安全的，因为调用者必须保证完整的物理内存在传递的`physical_memory_offset`处被映射到虚拟内存。
 pub unsafe fn translate_addr(addr: VirtAddr, physical_memory_offset: VirtAddr) -> Option<PhysAddr> {
-    translate_addr_inner(addr, physical_memory_offset)
+    translate_inner(addr, physical_memory_offset).map(|t| t.phys_addr)
 }
 
-/// 由 `translate_addr`调用的私有函数。
+/// 翻译给定的虚拟地址，同时返回映射的大小和页表项标志位
+///
+/// 支持 2MiB 和 1GiB 大页，如果地址没有被映射，则为`None'。
+///
+/// 这个函数是不安全的，因为调用者必须保证完整的物理内存在传递的`physical_memory_offset`处被映射到虚拟内存。
+pub unsafe fn translate(addr: VirtAddr, physical_memory_offset: VirtAddr) -> Option<Translation> {
+    translate_inner(addr, physical_memory_offset)
+}
+
+/// 由 `translate_addr` 和 `translate` 调用的私有函数。
 ///
 /// 这个函数是安全的，可以限制`unsafe`的范围，
-fn translate_addr_inner(addr: VirtAddr, physical_memory_offset: VirtAddr) -> Option<PhysAddr> {
+fn translate_inner(addr: VirtAddr, physical_memory_offset: VirtAddr) -> Option<Translation> {
     use x86_64::registers::control::Cr3;
-    use x86_64::structures::paging::page_table::FrameError;
     // 从 CR3 寄存器中读取活动的 4 级页表帧
     let (level_4_table_frame, _) = Cr3::read();
     // 把虚拟地址分成 4 个索引，分别对应 4 级页表
@@ -128,8 +250,9 @@ fn translate_addr_inner(addr: VirtAddr, physical_memory_offset: VirtAddr) -> Opt
     ];
     // 初始`frame`为4级页表帧，意为从4级页表开始遍历
     let mut frame = level_4_table_frame;
-    // 遍历 4 个索引
-    for &index in &table_indexes {
+    let mut flags = PageTableFlags::empty();
+    // 遍历 4 个索引，`level` 为 0 时对应 4 级页表
+    for (level, &index) in table_indexes.iter().enumerate() {
         let virt = physical_memory_offset + frame.start_address().as_u64();
         // 把虚拟地址转换为页表指针
         let table_ptr: *const PageTable = virt.as_ptr();
@@ -137,16 +260,34 @@ fn translate_addr_inner(addr: VirtAddr, physical_memory_offset: VirtAddr) -> Opt
         let table = unsafe { &*table_ptr };
         // 获取页表引用中的页表项
         let entry = &table[index];
-        // 读取页表条目并更新`frame`
+        flags = entry.flags();
         // 如果页表项没有映射到物理帧，则返回 None
-        // 因为我们不支持大页，所以如果页表项映射到大页，就直接恐慌
-        frame = match entry.frame() {
-            Ok(frame) => frame,
-            Err(FrameError::FrameNotPresent) => return None,
-            Err(FrameError::HugeFrame) => panic!("huge pages not supported"),
-        };
+        if !flags.contains(PageTableFlags::PRESENT) {
+            return None;
+        }
+        // 3 级和 2 级页表项可以直接映射 1GiB 和 2MiB 的大页
+        if flags.contains(PageTableFlags::HUGE_PAGE) {
+            let size = match level {
+                1 => MappingSize::Size1GiB,
+                2 => MappingSize::Size2MiB,
+                _ => return None, // 4 级和 1 级页表项中该位是保留位
+            };
+            // 大页的第 12 位是 PAT 位，不属于物理地址，需要按大页大小向下对齐
+            let base = entry.addr().align_down(size.bytes());
+            let offset = addr.as_u64() & (size.bytes() - 1);
+            return Some(Translation {
+                phys_addr: base + offset,
+                size,
+                flags,
+            });
+        }
+        // 读取页表条目并更新`frame`
+        frame = PhysFrame::containing_address(entry.addr());
     }
     // 最后返回物理帧的起始地址加上页内偏移量
-    Some(frame.start_address() + u64::from(addr.page_offset()))
+    Some(Translation {
+        phys_addr: frame.start_address() + u64::from(addr.page_offset()),
+        size: MappingSize::Size4KiB,
+        flags,
+    })
 }
-//////////////////////////////////////////////////////////////////// 可删 👆
@@ -0,0 +1,207 @@
+use super::{CONTIGUOUS_FRAMES, FRAME_ALLOCATOR};
+use crate::serial_println;
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
+
+const FRAME_SIZE: u64 = 4096;
+
+/// 启动内存 map 中最多能区分的区域类型数量
+const MAX_REGION_TYPES: usize = 16;
+
+/// 物理帧的使用者
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameOwner {
+    Heap,      // 内核堆
+    PageTable, // 映射时新建的页表
+    Stack,     // 内核栈
+    Slab,      // slab 缓存
+    Other,     // 其他用途，例如按需分配和写时复制的页面
+}
+
+impl FrameOwner {
+    const ALL: [FrameOwner; 5] = [
+        FrameOwner::Heap,
+        FrameOwner::PageTable,
+        FrameOwner::Stack,
+        FrameOwner::Slab,
+        FrameOwner::Other,
+    ];
+}
+
+/// 每个使用者当前持有的帧数，下标是 `FrameOwner as usize`
+static OWNED_FRAMES: [AtomicUsize; FrameOwner::ALL.len()] =
+    [const { AtomicUsize::new(0) }; FrameOwner::ALL.len()];
+
+/// 记录 `owner` 新分配了 `frames` 个帧
+pub fn charge(owner: FrameOwner, frames: usize) {
+    OWNED_FRAMES[owner as usize].fetch_add(frames, Ordering::Relaxed);
+}
+
+/// 记录 `owner` 归还了 `frames` 个帧
+///
+/// 写时复制的帧可能由和分配时不同的使用者释放，所以计数不会减到 0 以下
+pub fn uncharge(owner: FrameOwner, frames: usize) {
+    let _ = OWNED_FRAMES[owner as usize].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+        Some(n.saturating_sub(frames))
+    });
+}
+
+/// 返回 `owner` 当前持有的帧数
+pub fn owned_frames(owner: FrameOwner) -> usize {
+    OWNED_FRAMES[owner as usize].load(Ordering::Relaxed)
+}
+
+/// 包装一个帧分配器，把经过它分配和释放的帧记到 `owner` 名下
+///
+/// 通常在调用 `map_to` 时用 `FrameOwner::PageTable` 包装，统计新建的页表
+pub struct Accounted<'a, A> {
+    inner: &'a mut A,
+    owner: FrameOwner,
+}
+
+impl<'a, A> Accounted<'a, A> {
+    pub fn new(inner: &'a mut A, owner: FrameOwner) -> Self {
+        Accounted { inner, owner }
+    }
+}
+
+unsafe impl<A: FrameAllocator<Size4KiB>> FrameAllocator<Size4KiB> for Accounted<'_, A> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let frame = self.inner.allocate_frame()?;
+        charge(self.owner, 1);
+        Some(frame)
+    }
+}
+
+impl<A: FrameDeallocator<Size4KiB>> FrameDeallocator<Size4KiB> for Accounted<'_, A> {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        unsafe { self.inner.deallocate_frame(frame) };
+        uncharge(self.owner, 1);
+    }
+}
+
+/// 物理内存使用情况的快照，单位都是 4KiB 帧
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryStats {
+    pub total_frames: usize,      // 启动时可用的帧
+    pub used_frames: usize,       // 已经分配出去的帧
+    pub free_frames: usize,       // 空闲的帧
+    pub heap_frames: usize,       // 内核堆使用的帧
+    pub page_table_frames: usize, // 页表使用的帧
+    pub stack_frames: usize,      // 内核栈使用的帧
+    pub slab_frames: usize,       // slab 缓存使用的帧
+    pub other_frames: usize,      // 其他使用者的帧
+}
+
+impl fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kib = |frames: usize| frames as u64 * FRAME_SIZE / 1024;
+        writeln!(
+            f,
+            "total {} KiB, used {} KiB, free {} KiB",
+            kib(self.total_frames),
+            kib(self.used_frames),
+            kib(self.free_frames)
+        )?;
+        write!(
+            f,
+            "heap {} KiB, page tables {} KiB, stacks {} KiB, slabs {} KiB, other {} KiB",
+            kib(self.heap_frames),
+            kib(self.page_table_frames),
+            kib(self.stack_frames),
+            kib(self.slab_frames),
+            kib(self.other_frames)
+        )
+    }
+}
+
+/// 返回当前的物理内存使用情况
+///
+/// 可能在内存不足的诊断路径上调用，所以只使用 `try_lock`，
+/// 帧分配器正被占用或还没有 `install` 时返回 None
+pub fn stats() -> Option<MemoryStats> {
+    let guard = FRAME_ALLOCATOR.try_lock()?;
+    let frame_allocator = guard.as_ref()?;
+    // 连续内存池的帧虽然已经从位图中取出，但还没有分配出去的仍然算作空闲
+    let pool_free = CONTIGUOUS_FRAMES
+        .try_lock()?
+        .as_ref()
+        .map_or(0, |pool| pool.free_frames());
+    let free_frames = frame_allocator.free_frames() + pool_free;
+    Some(MemoryStats {
+        total_frames: frame_allocator.total_frames(),
+        used_frames: frame_allocator.total_frames() - free_frames,
+        free_frames,
+        heap_frames: owned_frames(FrameOwner::Heap),
+        page_table_frames: owned_frames(FrameOwner::PageTable),
+        stack_frames: owned_frames(FrameOwner::Stack),
+        slab_frames: owned_frames(FrameOwner::Slab),
+        other_frames: owned_frames(FrameOwner::Other),
+    })
+}
+
+/// 启动内存 map 按区域类型汇总的结果
+pub struct MemoryMapSummary {
+    entries: [Option<(MemoryRegionType, u64)>; MAX_REGION_TYPES], // 区域类型和总字节数
+    len: usize,
+}
+
+impl MemoryMapSummary {
+    /// 汇总启动内存 map，相同类型的区域会累加在一起
+    pub fn new(memory_map: &MemoryMap) -> Self {
+        const EMPTY: Option<(MemoryRegionType, u64)> = None;
+        let mut summary = MemoryMapSummary {
+            entries: [EMPTY; MAX_REGION_TYPES],
+            len: 0,
+        };
+        for region in memory_map.iter() {
+            let size = region.range.end_addr() - region.range.start_addr();
+            summary.add(region.region_type, size);
+        }
+        summary
+    }
+
+    fn add(&mut self, region_type: MemoryRegionType, size: u64) {
+        for (ty, bytes) in self.entries[..self.len].iter_mut().flatten() {
+            if *ty == region_type {
+                *bytes += size;
+                return;
+            }
+        }
+        // 类型太多时放不下的部分直接丢弃，只影响报告
+        if self.len < MAX_REGION_TYPES {
+            self.entries[self.len] = Some((region_type, size));
+            self.len += 1;
+        }
+    }
+
+    /// 返回某种类型的区域的总字节数
+    pub fn bytes(&self, region_type: MemoryRegionType) -> u64 {
+        self.iter()
+            .find(|&(ty, _)| ty == region_type)
+            .map_or(0, |(_, bytes)| bytes)
+    }
+
+    /// 按第一次出现的顺序遍历区域类型和总字节数
+    pub fn iter(&self) -> impl Iterator<Item = (MemoryRegionType, u64)> + '_ {
+        self.entries[..self.len].iter().map(|entry| entry.unwrap())
+    }
+}
+
+/// 把启动内存 map 按区域类型汇总后打印到串口
+pub fn report_memory_map(memory_map: &MemoryMap) {
+    serial_println!("boot memory map:");
+    for (region_type, bytes) in MemoryMapSummary::new(memory_map).iter() {
+        serial_println!("  {:?}: {} KiB", region_type, bytes / 1024);
+    }
+}
+
+/// 把当前的物理内存使用情况打印到串口
+pub fn report() {
+    match stats() {
+        Some(stats) => serial_println!("{}", stats),
+        None => serial_println!("memory stats unavailable"),
+    }
+}
@@ -0,0 +1,207 @@
+use super::accounting::{self, Accounted, FrameOwner};
+use super::vma::VmError;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags,
+    PhysFrame, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// 一个独立的地址空间，拥有自己的 4 级页表
+///
+/// 这个内核不是高半核，引导程序把内核、物理内存映射和内核虚拟地址空间放在
+/// 低半部分的几个 4 级页表项里。所以这里按 4 级页表项划分：创建时内核页表中
+/// 已经使用的项被共享，指向同一张 3 级页表，内核映射在所有地址空间中保持一致；
+/// 其余的项属于这个地址空间自己的用户部分，可以独立映射
+pub struct AddressSpace {
+    level_4_frame: PhysFrame, // 这个地址空间的 4 级页表所在的物理帧
+    phys_offset: VirtAddr,    // 物理内存映射的偏移量
+    shared: [u64; 8],         // 创建时从内核复制的 4 级页表项，每一位对应一项
+}
+
+impl AddressSpace {
+    /// 分配一张新的 4 级页表，并复制内核页表中所有已经使用的项
+    ///
+    /// 之后内核新增的 4 级页表项不会出现在这个地址空间中，所以应该在
+    /// 内核堆和其他内核区域初始化之后再创建地址空间
+    pub fn new() -> Result<Self, VmError> {
+        super::with_kernel_memory(|mapper, frame_allocator| {
+            let frame = frame_allocator
+                .allocate_frame()
+                .ok_or(MapToError::FrameAllocationFailed)?;
+            accounting::charge(FrameOwner::PageTable, 1);
+            let phys_offset = mapper.phys_offset();
+            let table = unsafe { table_at(phys_offset, frame) };
+            table.zero();
+            // 记下复制了哪些项，内核之后新增的项仍然属于这个地址空间自己
+            let mut shared = [0; 8];
+            for (index, entry) in mapper.level_4_table().iter().enumerate() {
+                if !entry.is_unused() {
+                    table[index] = entry.clone();
+                    shared[index / 64] |= 1 << (index % 64);
+                }
+            }
+            Ok(AddressSpace {
+                level_4_frame: frame,
+                phys_offset,
+                shared,
+            })
+        })
+        .expect("memory::install must be called before AddressSpace::new")
+    }
+
+    /// 这个地址空间的 4 级页表所在的物理帧，也就是激活时写入 CR3 的值
+    pub fn level_4_frame(&self) -> PhysFrame {
+        self.level_4_frame
+    }
+
+    /// 判断给定页面是否属于用户部分，也就是创建时没有从内核复制的 4 级页表项
+    pub fn is_user_page(&self, page: Page) -> bool {
+        !self.is_shared(usize::from(page.p4_index()))
+    }
+
+    /// 第 `index` 个 4 级页表项是否是创建时从内核复制的
+    fn is_shared(&self, index: usize) -> bool {
+        self.shared[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    /// 在用户部分分配一个清零的物理帧，并按 `flags` 映射到 `page`
+    ///
+    /// 会自动加上 USER_ACCESSIBLE。映射只修改这个地址空间的页表，
+    /// 在它被激活之前不会影响当前正在运行的代码
+    pub fn map_user(&mut self, page: Page, flags: PageTableFlags) -> Result<(), VmError> {
+        if !self.is_user_page(page) {
+            return Err(VmError::NotUserAddress);
+        }
+        let flags = flags | PageTableFlags::USER_ACCESSIBLE;
+        let mut mapper = self.mapper();
+        super::with_kernel_memory(|_, frame_allocator| {
+            let frame = frame_allocator
+                .allocate_frame()
+                .ok_or(MapToError::FrameAllocationFailed)?;
+            let virt = mapper.phys_offset() + frame.start_address().as_u64();
+            unsafe { core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, 4096) };
+            let mut page_tables = Accounted::new(&mut *frame_allocator, FrameOwner::PageTable);
+            match unsafe { mapper.map_to(page, frame, flags, &mut page_tables) } {
+                Ok(flush) => flush.flush(),
+                Err(err) => {
+                    unsafe { frame_allocator.deallocate_frame(frame) };
+                    return Err(err.into());
+                }
+            }
+            accounting::charge(FrameOwner::Other, 1);
+            Ok(())
+        })
+        .expect("memory::install must be called before AddressSpace::map_user")
+    }
+
+    /// 取消映射用户部分的一个页面，并释放它的物理帧
+    ///
+    /// 这个函数是不安全的，因为调用者必须保证这个页面不再被使用
+    pub unsafe fn unmap_user(&mut self, page: Page) -> Result<(), VmError> {
+        if !self.is_user_page(page) {
+            return Err(VmError::NotUserAddress);
+        }
+        let (frame, flush) = self.mapper().unmap(page)?;
+        flush.flush();
+        super::with_kernel_memory(|_, frame_allocator| unsafe {
+            frame_allocator.deallocate_frame(frame)
+        });
+        accounting::uncharge(FrameOwner::Other, 1);
+        Ok(())
+    }
+
+    /// 返回操作这个地址空间页表的映射器
+    pub fn mapper(&mut self) -> OffsetPageTable<'_> {
+        let table = unsafe { table_at(self.phys_offset, self.level_4_frame) };
+        unsafe { OffsetPageTable::new(table, self.phys_offset) }
+    }
+
+    /// 判断这个地址空间是否正在被使用
+    pub fn is_active(&self) -> bool {
+        Cr3::read().0 == self.level_4_frame
+    }
+
+    /// 把这个地址空间的 4 级页表加载到 CR3，切换到这个地址空间
+    ///
+    /// 这个函数是不安全的，因为调用者必须保证当前的代码、栈和之后要访问的
+    /// 内核数据都位于共享的 4 级页表项中，并且在这个地址空间被释放之前切换走
+    pub unsafe fn activate(&self) {
+        let (_, flags) = Cr3::read();
+        unsafe { Cr3::write(self.level_4_frame, flags) };
+    }
+}
+
+impl Drop for AddressSpace {
+    /// 释放用户部分的所有页面和页表，然后释放 4 级页表本身
+    ///
+    /// 如果这个地址空间正在使用，先切换回内核的页表
+    fn drop(&mut self) {
+        if self.is_active() {
+            activate_kernel();
+        }
+        let table = unsafe { table_at(self.phys_offset, self.level_4_frame) };
+        super::with_kernel_memory(|_, frame_allocator| {
+            for (index, entry) in table.iter_mut().enumerate() {
+                // 共享的项属于内核，不能释放
+                if entry.is_unused() || self.is_shared(index) {
+                    continue;
+                }
+                let frame = PhysFrame::containing_address(entry.addr());
+                unsafe { free_table(self.phys_offset, frame, 3, frame_allocator) };
+                entry.set_unused();
+            }
+            unsafe { frame_allocator.deallocate_frame(self.level_4_frame) };
+            accounting::uncharge(FrameOwner::PageTable, 1);
+        });
+    }
+}
+
+/// 切换回 `memory::init` 时的内核页表
+pub fn activate_kernel() {
+    let frame = super::with_kernel_memory(|mapper, _| {
+        let virt = VirtAddr::from_ptr(mapper.level_4_table() as *const PageTable);
+        PhysFrame::containing_address(PhysAddr::new(virt - mapper.phys_offset()))
+    })
+    .expect("memory::install must be called before activate_kernel");
+    let (_, flags) = Cr3::read();
+    unsafe { Cr3::write(frame, flags) };
+}
+
+/// 通过物理内存映射访问 `frame` 中的页表
+///
+/// 这个函数是不安全的，因为调用者必须保证 `frame` 确实是一张页表，
+/// 并且同一时间只有一个可变引用
+unsafe fn table_at<'a>(phys_offset: VirtAddr, frame: PhysFrame) -> &'a mut PageTable {
+    let virt = phys_offset + frame.start_address().as_u64();
+    unsafe { &mut *virt.as_mut_ptr() }
+}
+
+/// 释放 `level` 级页表 `frame` 下的所有页面和页表，最后释放 `frame` 本身
+///
+/// 这个函数是不安全的，因为调用者必须保证这些页面和页表都不再被使用
+unsafe fn free_table(
+    phys_offset: VirtAddr,
+    frame: PhysFrame,
+    level: u8,
+    frame_allocator: &mut impl FrameDeallocator<Size4KiB>,
+) {
+    let table = unsafe { table_at(phys_offset, frame) };
+    for entry in table.iter_mut() {
+        if entry.is_unused() {
+            continue;
+        }
+        let child = PhysFrame::containing_address(entry.addr());
+        if level == 1 {
+            unsafe { frame_allocator.deallocate_frame(child) };
+            accounting::uncharge(FrameOwner::Other, 1);
+        } else if !entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            unsafe { free_table(phys_offset, child, level - 1, frame_allocator) };
+        }
+        // 用户部分不会创建大页，即使有也不知道它的帧属于谁，只清除映射
+        entry.set_unused();
+    }
+    unsafe { frame_allocator.deallocate_frame(frame) };
+    accounting::uncharge(FrameOwner::PageTable, 1);
+}
@@ -0,0 +1,177 @@
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::slice;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+const FRAME_SIZE: u64 = 4096;
+const BITS_PER_WORD: usize = 64;
+
+/// 基于位图的物理帧分配器
+///
+/// 位图中每一位对应一个 4KiB 物理帧，1 表示空闲，0 表示已占用或不可用。
+/// 位图本身存放在第一个足够大的可用区域的开头，通过物理内存偏移映射访问
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u64], // 位图，每个 u64 管理 64 个帧
+    next: usize,                // 下一次开始搜索的字索引，它之前的字全部为 0
+    free_frames: usize,         // 当前空闲帧数量
+    total_frames: usize,        // 启动时所有可用帧的数量，包括位图自己占用的帧
+}
+
+impl BitmapFrameAllocator {
+    /// 从传递的内存 map 中创建一个位图帧分配器
+    ///
+    /// 这个函数是不安全的，因为调用者必须保证传递的内存 map 是有效的，
+    /// 并且完整的物理内存能在 `physical_memory_offset` 处被映射到虚拟内存。
+    /// 所有被标记为 "可用" 的帧都必须是真正未使用的，此函数只能调用一次
+    pub unsafe fn init(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) -> Self {
+        let usable_regions = || {
+            memory_map
+                .iter()
+                .filter(|r| r.region_type == MemoryRegionType::Usable)
+        };
+        // 位图需要覆盖到最高的可用帧
+        let max_addr = usable_regions()
+            .map(|r| r.range.end_addr())
+            .max()
+            .unwrap_or(0);
+        let frame_count = (max_addr / FRAME_SIZE) as usize;
+        let words = frame_count.div_ceil(BITS_PER_WORD);
+        let bitmap_size = (words * 8) as u64;
+        let bitmap_size = (bitmap_size + FRAME_SIZE - 1) & !(FRAME_SIZE - 1); // 向上对齐到整帧
+
+        // 找到第一个能放下位图的可用区域
+        let bitmap_start = usable_regions()
+            .find(|r| r.range.end_addr() - r.range.start_addr() >= bitmap_size)
+            .map(|r| r.range.start_addr())
+            .expect("no usable region large enough for the frame bitmap");
+
+        let virt = physical_memory_offset + bitmap_start;
+        let bitmap = unsafe { slice::from_raw_parts_mut(virt.as_mut_ptr::<u64>(), words) };
+        bitmap.fill(0); // 先把所有帧标记为不可用
+
+        let mut allocator = BitmapFrameAllocator {
+            bitmap,
+            next: 0,
+            free_frames: 0,
+            total_frames: 0,
+        };
+        // 标记所有可用帧为空闲
+        for region in usable_regions() {
+            let start = region.range.start_addr() / FRAME_SIZE;
+            let end = region.range.end_addr() / FRAME_SIZE;
+            for index in start..end {
+                allocator.set_free(index as usize);
+            }
+        }
+        allocator.total_frames = allocator.free_frames;
+        // 位图自己占用的帧不能再分配出去
+        let start = bitmap_start / FRAME_SIZE;
+        for index in start..start + bitmap_size / FRAME_SIZE {
+            allocator.set_used(index as usize);
+        }
+        allocator
+    }
+
+    /// 返回当前空闲帧的数量
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    /// 返回启动时可用帧的总数
+    pub fn total_frames(&self) -> usize {
+        self.total_frames
+    }
+
+    /// 分配 `count` 个物理上连续的帧，返回第一个帧
+    ///
+    /// 第一个帧的地址按 `align` 字节对齐，所有帧都位于 `limit` 之下。
+    /// 找不到满足条件的连续空闲帧时返回 None
+    pub fn allocate_contiguous(
+        &mut self,
+        count: usize,
+        align: u64,
+        limit: u64,
+    ) -> Option<PhysFrame> {
+        let align = (align / FRAME_SIZE).max(1) as usize;
+        let end = ((limit / FRAME_SIZE) as usize).min(self.bitmap.len() * BITS_PER_WORD);
+        // `next` 之前没有空闲帧，从那里开始找
+        let mut start = self.next * BITS_PER_WORD;
+        loop {
+            start = start.next_multiple_of(align);
+            if start + count > end {
+                return None;
+            }
+            // 从后往前检查，遇到已占用的帧就跳到它后面重新开始
+            match (start..start + count)
+                .rev()
+                .find(|&index| !self.is_free(index))
+            {
+                Some(used) => start = used + 1,
+                None => break,
+            }
+        }
+        for index in start..start + count {
+            self.set_used(index);
+        }
+        Some(PhysFrame::containing_address(PhysAddr::new(
+            start as u64 * FRAME_SIZE,
+        )))
+    }
+
+    /// 释放 `allocate_contiguous` 分配的 `count` 个连续帧
+    ///
+    /// 这个函数是不安全的，因为调用者必须保证这些帧不再被使用
+    pub unsafe fn deallocate_contiguous(&mut self, first: PhysFrame, count: usize) {
+        for i in 0..count as u64 {
+            unsafe { self.deallocate_frame(first + i) };
+        }
+    }
+
+    fn is_free(&self, index: usize) -> bool {
+        self.bitmap[index / BITS_PER_WORD] & (1 << (index % BITS_PER_WORD)) != 0
+    }
+
+    fn set_free(&mut self, index: usize) {
+        self.bitmap[index / BITS_PER_WORD] |= 1 << (index % BITS_PER_WORD);
+        self.free_frames += 1;
+    }
+
+    fn set_used(&mut self, index: usize) {
+        self.bitmap[index / BITS_PER_WORD] &= !(1 << (index % BITS_PER_WORD));
+        self.free_frames -= 1;
+    }
+}
+
+// 实现 `FrameAllocator<Size4KiB>` trait 用于 BitmapFrameAllocator
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        // `next` 之前的字都没有空闲帧，从 `next` 开始找第一个非 0 的字
+        while self.next < self.bitmap.len() {
+            let word = self.bitmap[self.next];
+            if word != 0 {
+                let index = self.next * BITS_PER_WORD + word.trailing_zeros() as usize;
+                self.set_used(index);
+                let addr = PhysAddr::new(index as u64 * FRAME_SIZE);
+                return Some(PhysFrame::containing_address(addr));
+            }
+            self.next += 1;
+        }
+        None // 物理内存耗尽
+    }
+}
+
+// 实现 `FrameDeallocator<Size4KiB>` trait，让取消映射的页面能归还它们的帧
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let index = (frame.start_address().as_u64() / FRAME_SIZE) as usize;
+        assert!(
+            index < self.bitmap.len() * BITS_PER_WORD,
+            "frame {:?} is not managed by this allocator",
+            frame
+        );
+        assert!(!self.is_free(index), "frame {:?} freed twice", frame);
+        self.set_free(index);
+        // 释放的帧可能在 `next` 之前，回退搜索起点
+        self.next = self.next.min(index / BITS_PER_WORD);
+    }
+}
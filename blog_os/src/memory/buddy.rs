@@ -0,0 +1,191 @@
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size2MiB, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+const FRAME_SIZE: u64 = Size4KiB::SIZE;
+
+/// 最大阶数，一个最大的块包含 2^MAX_ORDER 个帧 (4 MiB)
+pub const MAX_ORDER: usize = 10;
+
+/// 2MiB 大页对应的阶数
+const HUGE_PAGE_ORDER: usize = 9;
+
+/// 空闲块链表节点，直接存放在空闲块本身的物理内存里
+struct FreeBlock {
+    next: Option<&'static mut FreeBlock>,
+}
+
+/// 伙伴分配器，可以分配物理上连续的 2^order 个帧
+///
+/// 每个阶维护一个空闲块链表，分配时把大块对半拆分，
+/// 释放时如果伙伴块也空闲，就合并成更高一阶的块。
+/// 内核的实例是 `memory::CONTIGUOUS_FRAMES`，管理 `memory::install` 从位图中取出的内存池
+pub struct BuddyFrameAllocator {
+    free_lists: [Option<&'static mut FreeBlock>; MAX_ORDER + 1], // 每个阶一个空闲链表
+    physical_memory_offset: VirtAddr,                            // 通过物理内存偏移映射访问空闲块
+    free_frames: usize,                                          // 当前空闲帧数量
+}
+
+impl BuddyFrameAllocator {
+    /// 创建一个不管理任何内存的伙伴分配器，之后通过 `add_region` 添加内存
+    pub fn empty(physical_memory_offset: VirtAddr) -> Self {
+        const EMPTY: Option<&'static mut FreeBlock> = None;
+        BuddyFrameAllocator {
+            free_lists: [EMPTY; MAX_ORDER + 1],
+            physical_memory_offset,
+            free_frames: 0,
+        }
+    }
+
+    /// 把物理内存区域 `[start, end)` 交给分配器管理
+    ///
+    /// 这个函数是不安全的，因为调用者必须保证该区域是真正未使用的，并且没有交给其他帧分配器
+    pub unsafe fn add_region(&mut self, start: PhysAddr, end: PhysAddr) {
+        let mut start = start.align_up(FRAME_SIZE).as_u64();
+        let end = end.align_down(FRAME_SIZE).as_u64();
+        // 把区域切分成尽可能大的、按自身大小对齐的块
+        while start < end {
+            let mut order = MAX_ORDER;
+            while !start.is_multiple_of(block_size(order)) || start + block_size(order) > end {
+                order -= 1;
+            }
+            self.push(order, PhysAddr::new(start));
+            self.free_frames += 1 << order;
+            start += block_size(order);
+        }
+    }
+
+    /// 分配 2^order 个物理上连续的帧，返回起始物理地址
+    ///
+    /// 返回的地址按块大小对齐
+    pub fn allocate(&mut self, order: usize) -> Option<PhysAddr> {
+        // 找到不小于 `order` 的最小非空阶
+        let mut current = (order..=MAX_ORDER).find(|&o| self.free_lists[o].is_some())?;
+        let addr = self.pop(current).unwrap();
+        // 把多出来的部分对半拆分，后一半放回低一阶的链表
+        while current > order {
+            current -= 1;
+            self.push(current, addr + block_size(current));
+        }
+        self.free_frames -= 1 << order;
+        Some(addr)
+    }
+
+    /// 释放一个由 `allocate(order)` 分配的块，并尽可能与伙伴块合并
+    ///
+    /// 这个函数是不安全的，因为调用者必须保证该块不再被使用
+    pub unsafe fn deallocate(&mut self, addr: PhysAddr, order: usize) {
+        assert!(addr.is_aligned(block_size(order)), "misaligned buddy block");
+        self.free_frames += 1 << order;
+        let mut addr = addr.as_u64();
+        let mut order = order;
+        while order < MAX_ORDER {
+            // 伙伴块的地址只在第 order 位上不同
+            let buddy = addr ^ block_size(order);
+            if !self.remove(order, PhysAddr::new(buddy)) {
+                break; // 伙伴块不空闲，无法继续合并
+            }
+            addr = addr.min(buddy);
+            order += 1;
+        }
+        self.push(order, PhysAddr::new(addr));
+    }
+
+    /// 返回当前空闲帧的数量
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    /// 返回给定阶上空闲块的数量
+    pub fn free_blocks(&self, order: usize) -> usize {
+        let mut count = 0;
+        let mut current = &self.free_lists[order];
+        while let Some(block) = current {
+            count += 1;
+            current = &block.next;
+        }
+        count
+    }
+
+    /// 把一个空闲块放到对应阶链表的前端
+    fn push(&mut self, order: usize, addr: PhysAddr) {
+        let block = FreeBlock {
+            next: self.free_lists[order].take(),
+        };
+        let block_ptr: *mut FreeBlock = (self.physical_memory_offset + addr.as_u64()).as_mut_ptr();
+        unsafe {
+            block_ptr.write(block);
+            self.free_lists[order] = Some(&mut *block_ptr);
+        }
+    }
+
+    /// 从对应阶链表的前端取出一个空闲块
+    fn pop(&mut self, order: usize) -> Option<PhysAddr> {
+        let block = self.free_lists[order].take()?;
+        self.free_lists[order] = block.next.take();
+        Some(self.phys_addr(block))
+    }
+
+    /// 如果给定的块在对应阶的链表里，就把它移除并返回 true
+    fn remove(&mut self, order: usize, addr: PhysAddr) -> bool {
+        let physical_memory_offset = self.physical_memory_offset;
+        let mut current = &mut self.free_lists[order];
+        loop {
+            let found = match current {
+                Some(block) => {
+                    let virt = VirtAddr::from_ptr(&**block as *const FreeBlock);
+                    virt - physical_memory_offset == addr.as_u64()
+                }
+                None => return false,
+            };
+            if found {
+                let block = current.take().unwrap();
+                *current = block.next.take();
+                return true;
+            }
+            current = &mut current.as_mut().unwrap().next;
+        }
+    }
+
+    fn phys_addr(&self, block: &FreeBlock) -> PhysAddr {
+        let virt = VirtAddr::from_ptr(block as *const FreeBlock);
+        PhysAddr::new(virt - self.physical_memory_offset)
+    }
+}
+
+/// 返回给定阶的块大小 (字节)
+fn block_size(order: usize) -> u64 {
+    FRAME_SIZE << order
+}
+
+/// 返回能容纳 `size` 字节的最小阶
+pub fn order_for_size(size: usize) -> usize {
+    let frames = (size as u64).div_ceil(FRAME_SIZE).max(1);
+    frames.next_power_of_two().trailing_zeros() as usize
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        self.allocate(0).map(PhysFrame::containing_address)
+    }
+}
+
+unsafe impl FrameAllocator<Size2MiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        self.allocate(HUGE_PAGE_ORDER)
+            .map(PhysFrame::containing_address)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        unsafe { self.deallocate(frame.start_address(), 0) }
+    }
+}
+
+impl FrameDeallocator<Size2MiB> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size2MiB>) {
+        unsafe { self.deallocate(frame.start_address(), HUGE_PAGE_ORDER) }
+    }
+}
@@ -0,0 +1,202 @@
+use super::accounting::{self, Accounted, FrameOwner};
+use super::vma::VmError;
+use super::{FRAME_ALLOCATOR, MAPPER};
+use spin::Mutex;
+use x86_64::structures::paging::mapper::{MappedFrame, TranslateResult};
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTableFlags, PhysFrame,
+    Translate,
+};
+
+/// 标记写时复制页面的页表项位，第 9 位是留给操作系统使用的位
+pub const COW: PageTableFlags = PageTableFlags::BIT_9;
+
+/// 最多能同时被写时复制共享的帧数
+const MAX_SHARED_FRAMES: usize = 256;
+
+/// 被写时复制共享的帧的引用计数，没有记录的帧只有一个使用者
+///
+/// 页错误处理函数也会修改它，所以用固定大小的表，不在堆上分配或释放内存
+struct RefCounts {
+    entries: [Option<(PhysFrame, usize)>; MAX_SHARED_FRAMES], // 只记录计数至少为 2 的帧
+}
+
+impl RefCounts {
+    const fn new() -> Self {
+        RefCounts {
+            entries: [None; MAX_SHARED_FRAMES],
+        }
+    }
+
+    /// 帧的使用者数量
+    fn get(&self, frame: PhysFrame) -> usize {
+        self.entries
+            .iter()
+            .flatten()
+            .find(|(shared, _)| *shared == frame)
+            .map_or(1, |&(_, count)| count)
+    }
+
+    /// 增加一个使用者，表已满时返回错误
+    fn increment(&mut self, frame: PhysFrame) -> Result<(), VmError> {
+        if let Some((_, count)) = self.entries.iter_mut().flatten().find(|(f, _)| *f == frame) {
+            *count += 1;
+            return Ok(());
+        }
+        let slot = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.is_none())
+            .ok_or(VmError::TooManySharedFrames)?;
+        *slot = Some((frame, 2));
+        Ok(())
+    }
+
+    /// 减少一个使用者，返回剩下的使用者数量
+    ///
+    /// 只剩一个使用者时删除记录，没有记录的帧返回 0
+    fn decrement(&mut self, frame: PhysFrame) -> usize {
+        let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.is_some_and(|(f, _)| f == frame))
+        else {
+            return 0;
+        };
+        let (_, count) = entry.as_mut().unwrap();
+        *count -= 1;
+        let count = *count;
+        if count == 1 {
+            *entry = None;
+        }
+        count
+    }
+}
+
+static REF_COUNTS: Mutex<RefCounts> = Mutex::new(RefCounts::new());
+
+/// 把已经映射的 `src` 页面以写时复制的方式共享到还没有映射的 `dst` 页面
+///
+/// 两个页面都会指向同一个物理帧，并被映射为只读且带上 `COW` 标记。
+/// 之后任何一方写入时，页错误处理函数会为它复制一个私有的帧
+pub fn share(src: Page, dst: Page) -> Result<(), VmError> {
+    let (frame, flags) = super::with_kernel_memory(|mapper, _| mapped_frame(mapper, src))
+        .expect("memory::install must be called before cow::share")
+        .ok_or(VmError::PageNotMapped)?;
+    REF_COUNTS.lock().increment(frame)?;
+
+    let shared_flags = (flags - PageTableFlags::WRITABLE) | COW;
+    let result = super::with_kernel_memory(|mapper, frame_allocator| {
+        unsafe {
+            mapper.update_flags(src, shared_flags)?.flush();
+            let mut page_tables = Accounted::new(frame_allocator, FrameOwner::PageTable);
+            mapper
+                .map_to(dst, frame, shared_flags, &mut page_tables)?
+                .flush();
+        }
+        Ok(())
+    })
+    .unwrap();
+    if result.is_err() {
+        release_frame(frame); // 映射失败，撤销引用计数
+    }
+    result
+}
+
+/// 取消映射一个写时复制页面，最后一个使用者取消映射时释放物理帧
+///
+/// 这个函数是不安全的，因为调用者必须保证这个页面不再被使用
+pub unsafe fn unmap(page: Page) -> Result<(), VmError> {
+    let frame = super::with_kernel_memory(|mapper, _| {
+        let (frame, flush) = mapper.unmap(page)?;
+        flush.flush();
+        Ok::<_, VmError>(frame)
+    })
+    .expect("memory::install must be called before cow::unmap")?;
+    if release_frame(frame) {
+        super::with_kernel_memory(|_, frame_allocator| unsafe {
+            frame_allocator.deallocate_frame(frame)
+        });
+        accounting::uncharge(FrameOwner::Other, 1);
+    }
+    Ok(())
+}
+
+/// 处理对写时复制页面的写入，成功时返回 true
+///
+/// 由页错误处理函数调用，所以只使用 `try_lock`
+pub(super) fn handle_write_fault(page: Page) -> bool {
+    let (Some(mut mapper), Some(mut frame_allocator), Some(mut ref_counts)) = (
+        MAPPER.try_lock(),
+        FRAME_ALLOCATOR.try_lock(),
+        REF_COUNTS.try_lock(),
+    ) else {
+        return false;
+    };
+    let (Some(mapper), Some(frame_allocator)) = (mapper.as_mut(), frame_allocator.as_mut()) else {
+        return false;
+    };
+    let Some((frame, flags)) = mapped_frame(mapper, page) else {
+        return false;
+    };
+    if !flags.contains(COW) {
+        return false; // 真正的只读页面
+    }
+    let private_flags = (flags - COW) | PageTableFlags::WRITABLE;
+    if ref_counts.get(frame) <= 1 {
+        // 只剩这一个使用者，直接恢复写权限，不需要复制
+        return match unsafe { mapper.update_flags(page, private_flags) } {
+            Ok(flush) => {
+                flush.flush();
+                true
+            }
+            Err(_) => false,
+        };
+    }
+
+    // 还有其他使用者，复制一个私有的帧
+    let Some(new_frame) = frame_allocator.allocate_frame() else {
+        return false; // 物理内存耗尽
+    };
+    let phys_offset = mapper.phys_offset();
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            (phys_offset + frame.start_address().as_u64()).as_ptr::<u8>(),
+            (phys_offset + new_frame.start_address().as_u64()).as_mut_ptr::<u8>(),
+            4096,
+        );
+    }
+    let remapped = mapper.unmap(page).map(|(_, flush)| flush.flush()).is_ok()
+        && unsafe {
+            let mut page_tables = Accounted::new(&mut *frame_allocator, FrameOwner::PageTable);
+            mapper.map_to(page, new_frame, private_flags, &mut page_tables)
+        }
+        .map(|flush| flush.flush())
+        .is_ok();
+    if !remapped {
+        unsafe { frame_allocator.deallocate_frame(new_frame) };
+        return false;
+    }
+    // 只剩一个使用者时删除记录，之后由它取消映射时释放原来的帧
+    ref_counts.decrement(frame);
+    accounting::charge(FrameOwner::Other, 1);
+    true
+}
+
+/// 返回页面映射到的 4KiB 帧和页表项标志位
+fn mapped_frame(mapper: &OffsetPageTable, page: Page) -> Option<(PhysFrame, PageTableFlags)> {
+    match mapper.translate(page.start_address()) {
+        TranslateResult::Mapped {
+            frame: MappedFrame::Size4KiB(frame),
+            flags,
+            ..
+        } => Some((frame, flags)),
+        _ => None,
+    }
+}
+
+/// 减少帧的引用计数，如果已经没有使用者返回 true
+fn release_frame(frame: PhysFrame) -> bool {
+    // 没有记录的帧只有调用者一个使用者
+    REF_COUNTS.lock().decrement(frame) == 0
+}
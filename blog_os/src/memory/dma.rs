@@ -0,0 +1,132 @@
+use super::accounting::{self, FrameOwner};
+use super::buddy;
+use core::slice;
+use x86_64::structures::paging::PhysFrame;
+use x86_64::{PhysAddr, VirtAddr};
+
+/// ISA DMA 只能访问 16 MiB 以下的物理内存
+pub const ISA_DMA_LIMIT: u64 = 16 * 1024 * 1024;
+/// 只支持 32 位地址的设备只能访问 4 GiB 以下的物理内存
+pub const DMA32_LIMIT: u64 = 4 * 1024 * 1024 * 1024;
+
+const FRAME_SIZE: u64 = 4096;
+
+/// 一段物理上连续、可以交给设备做 DMA 的缓冲区
+///
+/// 通过物理内存映射访问，不占用内核虚拟地址空间。x86_64 上 DMA 和 CPU 缓存
+/// 保持一致，所以使用普通的缓存方式。被丢弃时把物理帧还给分配它的分配器
+pub struct DmaBuffer {
+    virt: VirtAddr,       // 通过物理内存映射访问缓冲区的虚拟地址
+    first: PhysFrame,     // 第一个物理帧
+    frames: usize,        // 占用的帧数
+    order: Option<usize>, // 从连续内存池分配时伙伴块的阶，None 表示来自帧分配器
+    len: usize,           // 请求的字节数
+}
+
+impl DmaBuffer {
+    /// 分配一段至少 `len` 字节、清零的连续物理内存
+    ///
+    /// 物理起始地址按 `align` 字节对齐 (至少按页对齐)，整个缓冲区都位于
+    /// 物理地址 `limit` 之下。优先从连续内存池分配，池中没有合适的块或者池不在
+    /// `limit` 之下时，再从帧分配器中查找连续的空闲帧。找不到满足条件的内存时返回 None
+    pub fn new(len: usize, align: u64, limit: u64) -> Option<Self> {
+        let frames = (len.max(1) as u64).div_ceil(FRAME_SIZE) as usize;
+        let phys_offset = super::with_kernel_memory(|mapper, _| mapper.phys_offset())
+            .expect("memory::install must be called before DmaBuffer::new");
+        let (first, frames, order) = match allocate_from_pool(frames, align, limit) {
+            Some((first, order)) => (first, 1 << order, Some(order)),
+            None => {
+                let first = super::with_kernel_memory(|_, frame_allocator| {
+                    frame_allocator.allocate_contiguous(frames, align, limit)
+                })
+                .unwrap()?;
+                (first, frames, None)
+            }
+        };
+        accounting::charge(FrameOwner::Other, frames);
+        let virt = phys_offset + first.start_address().as_u64();
+        unsafe {
+            virt.as_mut_ptr::<u8>()
+                .write_bytes(0, frames * FRAME_SIZE as usize)
+        };
+        Some(DmaBuffer {
+            virt,
+            first,
+            frames,
+            order,
+            len,
+        })
+    }
+
+    /// 缓冲区的物理地址，交给设备使用
+    pub fn phys_addr(&self) -> PhysAddr {
+        self.first.start_address()
+    }
+
+    /// 缓冲区的虚拟地址，给 CPU 使用
+    pub fn virt_addr(&self) -> VirtAddr {
+        self.virt
+    }
+
+    /// 请求的字节数
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// 缓冲区是否为空
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// 指向缓冲区开头的指针
+    pub fn as_ptr(&self) -> *const u8 {
+        self.virt.as_ptr()
+    }
+
+    /// 指向缓冲区开头的可变指针
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.virt.as_mut_ptr()
+    }
+
+    /// 把缓冲区当作字节切片访问
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.as_ptr(), self.len) }
+    }
+
+    /// 把缓冲区当作可变字节切片访问
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.as_mut_ptr(), self.len) }
+    }
+}
+
+impl Drop for DmaBuffer {
+    /// 把物理帧还给分配它的分配器，设备必须已经停止访问这段内存
+    fn drop(&mut self) {
+        match self.order {
+            Some(order) => super::with_contiguous_frames(|pool| unsafe {
+                pool.deallocate(self.first.start_address(), order)
+            }),
+            None => super::with_kernel_memory(|_, frame_allocator| unsafe {
+                frame_allocator.deallocate_contiguous(self.first, self.frames)
+            }),
+        };
+        accounting::uncharge(FrameOwner::Other, self.frames);
+    }
+}
+
+/// 从连续内存池分配能放下 `frames` 个帧的伙伴块，返回第一个帧和块的阶
+///
+/// 伙伴块按自身大小对齐，所以取满足大小和对齐要求的较大的阶
+fn allocate_from_pool(frames: usize, align: u64, limit: u64) -> Option<(PhysFrame, usize)> {
+    let size = frames as u64 * FRAME_SIZE;
+    let order = buddy::order_for_size(size.max(align) as usize);
+    super::with_contiguous_frames(|pool| {
+        let addr = pool.allocate(order)?;
+        if addr.as_u64() + (FRAME_SIZE << order) > limit {
+            // 池不在 `limit` 之下，交给帧分配器处理
+            unsafe { pool.deallocate(addr, order) };
+            return None;
+        }
+        Some((PhysFrame::containing_address(addr), order))
+    })?
+}
@@ -0,0 +1,141 @@
+use super::MappingSize;
+use crate::serial_println;
+use core::fmt;
+use x86_64::structures::paging::{OffsetPageTable, PageTable, PageTableFlags};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// 比较和显示映射时忽略的标志位
+///
+/// ACCESSED 和 DIRTY 由 CPU 设置，每次启动都可能不同；
+/// HUGE_PAGE 已经体现在映射大小里
+const IGNORED_FLAGS: PageTableFlags = PageTableFlags::ACCESSED
+    .union(PageTableFlags::DIRTY)
+    .union(PageTableFlags::HUGE_PAGE);
+
+/// 一段连续映射的虚拟地址区间
+///
+/// 区间内的页大小和标志位都相同，并且映射到连续的物理地址
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MappedRange {
+    pub start: VirtAddr,        // 虚拟起始地址
+    pub size: u64,              // 区间大小 (字节)
+    pub phys_start: PhysAddr,   // 映射到的物理起始地址
+    pub page_size: MappingSize, // 每一页的大小
+    pub flags: PageTableFlags,  // 最后一级页表项的标志位
+}
+
+impl MappedRange {
+    /// 区间的结束地址 (不含)
+    pub fn end(&self) -> VirtAddr {
+        self.start + self.size
+    }
+
+    /// 区间包含的页数
+    pub fn pages(&self) -> u64 {
+        self.size / self.page_size.bytes()
+    }
+
+    /// 如果下一页紧跟在这个区间之后并且属性相同，把它合并进来
+    fn try_extend(&mut self, next: &MappedRange) -> bool {
+        let contiguous = self.end() == next.start && self.phys_start + self.size == next.phys_start;
+        if !contiguous || self.page_size != next.page_size || self.flags != next.flags {
+            return false;
+        }
+        self.size += next.size;
+        true
+    }
+}
+
+impl fmt::Display for MappedRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let page_size = match self.page_size {
+            MappingSize::Size4KiB => "4K",
+            MappingSize::Size2MiB => "2M",
+            MappingSize::Size1GiB => "1G",
+        };
+        write!(
+            f,
+            "{:#018x}-{:#018x} -> {:#014x} {:>6} x {} {:?}",
+            self.start.as_u64(),
+            self.end().as_u64(),
+            self.phys_start.as_u64(),
+            self.pages(),
+            page_size,
+            self.flags
+        )
+    }
+}
+
+/// 遍历当前 4 级页表中所有已映射的区间，按虚拟地址从小到大调用 `f`
+///
+/// 相邻且属性相同的页面会合并成一个区间。只看最后一级页表项的标志位，
+/// 上级页表项的限制 (例如上级没有 WRITABLE) 不会反映在结果中。
+/// 只读取页表，需要 `&mut` 只是因为 `OffsetPageTable` 只提供可变的 4 级页表引用
+pub fn for_each_range(mapper: &mut OffsetPageTable, mut f: impl FnMut(MappedRange)) {
+    let phys_offset = mapper.phys_offset();
+    let mut pending: Option<MappedRange> = None;
+    let mut emit = |range: MappedRange| {
+        let merged = pending
+            .as_mut()
+            .is_some_and(|current| current.try_extend(&range));
+        if !merged && let Some(done) = pending.replace(range) {
+            f(done);
+        }
+    };
+    walk(mapper.level_4_table(), phys_offset, 4, 0, &mut emit);
+    if let Some(done) = pending {
+        f(done);
+    }
+}
+
+/// 把当前地址空间的映射列表打印到串口，每行一个区间
+pub fn dump(mapper: &mut OffsetPageTable) {
+    serial_println!("virtual range                             -> physical        pages   flags");
+    let mut total = 0;
+    for_each_range(mapper, |range| {
+        serial_println!("{}", range);
+        total += 1;
+    });
+    serial_println!("{} mapped ranges", total);
+}
+
+/// 递归遍历 `level` 级页表，`base` 是这张表覆盖的第一个虚拟地址
+fn walk(
+    table: &PageTable,
+    phys_offset: VirtAddr,
+    level: u8,
+    base: u64,
+    emit: &mut impl FnMut(MappedRange),
+) {
+    // 每一级页表项覆盖的字节数：4 级 512GiB，3 级 1GiB，2 级 2MiB，1 级 4KiB
+    let entry_size = 1u64 << (12 + 9 * (level as u64 - 1));
+    for (index, entry) in table.iter().enumerate() {
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        // 虚拟地址的第 47 位需要符号扩展
+        let start = VirtAddr::new_truncate(base + index as u64 * entry_size);
+        let page_size = match level {
+            1 => Some(MappingSize::Size4KiB),
+            2 if flags.contains(PageTableFlags::HUGE_PAGE) => Some(MappingSize::Size2MiB),
+            3 if flags.contains(PageTableFlags::HUGE_PAGE) => Some(MappingSize::Size1GiB),
+            _ => None,
+        };
+        match page_size {
+            Some(page_size) => emit(MappedRange {
+                start,
+                size: page_size.bytes(),
+                // 大页的第 12 位是 PAT 位，不属于物理地址
+                phys_start: entry.addr().align_down(page_size.bytes()),
+                page_size,
+                flags: flags - IGNORED_FLAGS,
+            }),
+            None => {
+                let virt = phys_offset + entry.addr().as_u64();
+                let next: &PageTable = unsafe { &*virt.as_ptr() };
+                walk(next, phys_offset, level - 1, start.as_u64(), emit);
+            }
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use super::accounting::{self, Accounted, FrameOwner};
+use super::{FRAME_ALLOCATOR, MAPPER, cow, vma::KERNEL_SPACE};
+use x86_64::VirtAddr;
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags};
+
+/// 尝试修复一次页错误
+///
+/// 返回 true 表示页错误已经被处理，触发它的指令可以重新执行；
+/// 返回 false 表示这是一次真正的错误，调用者应该报告并停机。
+///
+/// 由页错误处理函数调用，此时中断已经关闭。发生页错误时内核可能正持有
+/// 某个锁，所以这里只使用 `try_lock`，拿不到锁就当作真正的错误处理
+pub fn handle_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> bool {
+    // 写入只读页面可能是写时复制
+    if error_code
+        .contains(PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE)
+    {
+        return cow::handle_write_fault(Page::containing_address(addr));
+    }
+    // 只有访问不存在的页面才可能是按需分配
+    if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        return false;
+    }
+    let area = match KERNEL_SPACE.try_lock() {
+        Some(kernel_space) => kernel_space.find(addr),
+        None => return false,
+    };
+    match area {
+        Some(area) if area.lazy => {
+            let page = Page::containing_address(addr);
+            map_zeroed_page(page, area.flags, area.kind.frame_owner())
+        }
+        _ => false,
+    }
+}
+
+/// 分配一个清零的物理帧，并按给定标志位映射到 `page`，帧记到 `owner` 名下
+fn map_zeroed_page(page: Page, flags: PageTableFlags, owner: FrameOwner) -> bool {
+    let (Some(mut mapper), Some(mut frame_allocator)) =
+        (MAPPER.try_lock(), FRAME_ALLOCATOR.try_lock())
+    else {
+        return false;
+    };
+    let (Some(mapper), Some(frame_allocator)) = (mapper.as_mut(), frame_allocator.as_mut()) else {
+        return false;
+    };
+    let Some(frame) = frame_allocator.allocate_frame() else {
+        return false; // 物理内存耗尽
+    };
+    // 在映射之前通过物理内存偏移映射清零，这样只读的区域也能正确初始化
+    let virt = mapper.phys_offset() + frame.start_address().as_u64();
+    unsafe { core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, 4096) };
+    let mut page_tables = Accounted::new(&mut *frame_allocator, FrameOwner::PageTable);
+    match unsafe { mapper.map_to(page, frame, flags, &mut page_tables) } {
+        Ok(flush) => {
+            flush.flush();
+            accounting::charge(owner, 1);
+            true
+        }
+        Err(_) => {
+            unsafe { frame_allocator.deallocate_frame(frame) };
+            false
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use super::accounting::{Accounted, FrameOwner};
+use super::vma::{AreaKind, KERNEL_SPACE, VmError};
+use core::marker::PhantomData;
+use core::mem;
+use core::ops::{Deref, DerefMut};
+use x86_64::structures::paging::mapper::UnmapError;
+use x86_64::structures::paging::page::PageRange;
+use x86_64::structures::paging::{Mapper, PageTableFlags, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// 设备内存的缓存方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    Uncached,     // 不缓存，读写都直接访问设备，用于大多数寄存器
+    WriteThrough, // 读可以缓存，写会立即写到设备，用于帧缓冲区等
+}
+
+impl CacheMode {
+    /// 对应的页表标志位
+    fn flags(self) -> PageTableFlags {
+        match self {
+            // PCD 和 PWT 同时设置，在默认的 PAT 下是强不可缓存 (UC)
+            CacheMode::Uncached => PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH,
+            CacheMode::WriteThrough => PageTableFlags::WRITE_THROUGH,
+        }
+    }
+}
+
+/// 映射到内核虚拟地址空间中的一段设备内存，按 `T` 的布局访问
+///
+/// `T` 通常是由 `Volatile` 字段组成的 `#[repr(C)]` 寄存器块，
+/// 这样每次访问寄存器都是一次真正的读写。被丢弃时自动取消映射
+pub struct IoMem<T> {
+    virt: VirtAddr,   // `T` 所在的虚拟地址，保留了物理地址的页内偏移
+    pages: PageRange, // 映射的所有页，第一页也是内核虚拟地址空间中区域的起始地址
+    _marker: PhantomData<T>,
+}
+
+/// 把从 `phys` 开始的设备内存映射到内核虚拟地址空间，按 `T` 的布局访问
+///
+/// 映射的大小是 `size_of::<T>()`，`phys` 不需要按页对齐，但必须按 `T` 对齐。
+/// 页面不可执行，并按 `cache` 设置缓存方式。设备内存不属于帧分配器，
+/// 取消映射时不会释放这些物理帧
+pub fn ioremap<T>(phys: PhysAddr, cache: CacheMode) -> Result<IoMem<T>, VmError> {
+    assert!(
+        phys.is_aligned(mem::align_of::<T>() as u64),
+        "MMIO address {:?} is not aligned for the register block",
+        phys
+    );
+    let size = mem::size_of::<T>().max(1) as u64;
+    let first_frame = PhysFrame::containing_address(phys);
+    let last_frame = PhysFrame::containing_address(phys + (size - 1));
+    let frames = PhysFrame::range_inclusive(first_frame, last_frame);
+
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_EXECUTE
+        | cache.flags();
+    let area = KERNEL_SPACE
+        .lock()
+        .reserve(frames.count() as u64 * 4096, AreaKind::Mmio, flags)?;
+    let io_mem = IoMem {
+        virt: area.start + (phys - first_frame.start_address()),
+        pages: area.pages(),
+        _marker: PhantomData,
+    };
+    let mapped = super::with_kernel_memory(|mapper, frame_allocator| {
+        let mut page_tables = Accounted::new(frame_allocator, FrameOwner::PageTable);
+        for (page, frame) in io_mem.pages.zip(frames) {
+            unsafe { mapper.map_to(page, frame, flags, &mut page_tables)?.flush() };
+        }
+        Ok(())
+    })
+    .expect("memory::install must be called before ioremap");
+    // 出错时 `io_mem` 被丢弃，已经映射的页面会被取消映射
+    mapped.map(|()| io_mem)
+}
+
+impl<T> IoMem<T> {
+    /// 映射后 `T` 所在的虚拟地址
+    pub fn virt_addr(&self) -> VirtAddr {
+        self.virt
+    }
+}
+
+impl<T> Deref for IoMem<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.virt.as_ptr() }
+    }
+}
+
+impl<T> DerefMut for IoMem<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.virt.as_mut_ptr() }
+    }
+}
+
+impl<T> Drop for IoMem<T> {
+    /// 取消映射所有页面并释放虚拟地址区域，物理帧属于设备，不会被释放
+    fn drop(&mut self) {
+        let pages = self.pages;
+        super::with_kernel_memory(|mapper, _| {
+            for page in pages {
+                match mapper.unmap(page) {
+                    Ok((_, flush)) => flush.flush(),
+                    Err(UnmapError::PageNotMapped) => {} // 映射中途失败时后面的页面没有映射
+                    Err(err) => panic!("failed to unmap MMIO page {:?}: {:?}", page, err),
+                }
+            }
+        });
+        KERNEL_SPACE
+            .lock()
+            .release(pages.start.start_address())
+            .expect("MMIO area was already released");
+    }
+}
@@ -0,0 +1,111 @@
+use super::accounting::FrameOwner;
+use super::vma::{self, AreaKind, KERNEL_SPACE, VmError};
+use super::wx;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::Page;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// 一个从内核虚拟地址空间中分配的栈
+///
+/// 栈底下方有一个不映射的保护页，栈溢出时会触发页错误，而不是悄悄破坏相邻的内存
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernelStack {
+    guard: VirtAddr, // 保护页的起始地址，也是整个区域的起始地址
+    top: VirtAddr,   // 栈顶 (不含)，栈从这里向下增长
+}
+
+impl KernelStack {
+    /// 栈顶地址，加载到 rsp 或 TSS 中
+    pub fn top(&self) -> VirtAddr {
+        self.top
+    }
+
+    /// 栈底地址，即最低的可用地址
+    pub fn bottom(&self) -> VirtAddr {
+        self.guard + PAGE_SIZE
+    }
+
+    /// 栈底下方的保护页
+    pub fn guard_page(&self) -> Page {
+        Page::containing_address(self.guard)
+    }
+
+    /// 判断给定地址是否在栈的可用范围内
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.bottom() <= addr && addr < self.top
+    }
+}
+
+/// 分配一个 `pages` 页大小的内核栈，并在栈底下方保留一个不映射的保护页
+///
+/// 必须在 `memory::install` 之后调用
+pub fn alloc_stack(pages: u64) -> Result<KernelStack, VmError> {
+    let flags = wx::DATA_FLAGS;
+    // 保护页也属于这个区域，这样它不会被分配给别人
+    let area = KERNEL_SPACE
+        .lock()
+        .reserve((pages + 1) * PAGE_SIZE, AreaKind::Stack, flags)?;
+    let stack = KernelStack {
+        guard: area.start,
+        top: area.end(),
+    };
+    // 只映射保护页之上的部分
+    let first = Page::containing_address(stack.bottom());
+    let end = Page::containing_address(stack.top);
+    let mapped = super::with_kernel_memory(|mapper, frame_allocator| {
+        vma::map_pages(
+            Page::range(first, end),
+            flags,
+            FrameOwner::Stack,
+            mapper,
+            frame_allocator,
+        )
+    })
+    .expect("memory::install must be called before alloc_stack");
+    if let Err(err) = mapped {
+        unsafe { free_stack(stack)? };
+        return Err(err);
+    }
+    Ok(stack)
+}
+
+/// 取消映射并释放一个内核栈
+///
+/// 这个函数是不安全的，因为调用者必须保证没有代码还在使用这个栈
+pub unsafe fn free_stack(stack: KernelStack) -> Result<(), VmError> {
+    let mut kernel_space = KERNEL_SPACE.lock();
+    super::with_kernel_memory(|mapper, frame_allocator| unsafe {
+        kernel_space.unmap(stack.guard, mapper, frame_allocator)
+    })
+    .expect("memory::install must be called before free_stack")?;
+    kernel_space.release(stack.guard)?;
+    Ok(())
+}
+
+/// 如果给定地址落在某个内核栈的保护页里，返回这个栈
+///
+/// 用于在页错误和 double fault 处理函数中诊断栈溢出，
+/// 所以只使用 `try_lock`，拿不到锁时返回 None
+pub fn guard_page_hit(addr: VirtAddr) -> Option<KernelStack> {
+    stack_area(addr).filter(|stack| !stack.contains(addr))
+}
+
+/// 如果给定地址落在某个内核栈的可用范围里，返回这个栈
+///
+/// 分配追踪用它确定回溯时可以读取的范围，所以同样只使用 `try_lock`
+pub fn containing(addr: VirtAddr) -> Option<KernelStack> {
+    stack_area(addr).filter(|stack| stack.contains(addr))
+}
+
+/// 包含给定地址的内核栈区域，包括保护页
+fn stack_area(addr: VirtAddr) -> Option<KernelStack> {
+    let area = KERNEL_SPACE.try_lock()?.find(addr)?;
+    if area.kind != AreaKind::Stack {
+        return None;
+    }
+    Some(KernelStack {
+        guard: area.start,
+        top: area.end(),
+    })
+}
@@ -0,0 +1,314 @@
+use super::accounting::{self, Accounted, FrameOwner};
+use spin::Mutex;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::mapper::{FlagUpdateError, MapToError, UnmapError};
+use x86_64::structures::paging::page::PageRange;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, Page, PageSize, PageTableFlags, Size4KiB,
+};
+
+/// 内核虚拟地址空间的起始地址，沿用原来堆的地址
+pub const KERNEL_SPACE_START: u64 = 0x_4444_4444_0000;
+/// 内核虚拟地址空间的结束地址 (不含)
+///
+/// 整个区间位于同一个 4 级页表项 (第 136 项) 之内
+pub const KERNEL_SPACE_END: u64 = 0x_4480_0000_0000;
+
+/// 最多能同时存在的区域数量
+///
+/// 区域表放在固定大小的数组里，这样在堆初始化之前也能使用
+const MAX_AREAS: usize = 64;
+
+const PAGE_SIZE: u64 = Size4KiB::SIZE;
+
+/// 内核的虚拟地址空间管理器
+///
+/// 在内核初始化堆之前就需要可用，所以是一个 `spin::Mutex` 而不是放在堆上
+pub static KERNEL_SPACE: Mutex<VirtualMemoryManager> = Mutex::new(VirtualMemoryManager::new(
+    VirtAddr::new_truncate(KERNEL_SPACE_START),
+    VirtAddr::new_truncate(KERNEL_SPACE_END),
+));
+
+/// 虚拟内存区域的用途
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AreaKind {
+    Heap,    // 内核堆
+    Stack,   // 内核栈
+    Mmio,    // 设备寄存器映射
+    Vmalloc, // vmalloc 分配的缓冲区
+    Other,   // 其他用途
+}
+
+impl AreaKind {
+    /// 这种区域的物理帧在内存统计中的使用者
+    pub fn frame_owner(self) -> FrameOwner {
+        match self {
+            AreaKind::Heap => FrameOwner::Heap,
+            AreaKind::Stack => FrameOwner::Stack,
+            AreaKind::Mmio | AreaKind::Vmalloc | AreaKind::Other => FrameOwner::Other,
+        }
+    }
+}
+
+/// 一段连续的、按页对齐的虚拟内存区域
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmArea {
+    pub start: VirtAddr,       // 起始地址，按页对齐
+    pub size: u64,             // 区域大小 (字节)，是页大小的整数倍
+    pub kind: AreaKind,        // 区域的用途
+    pub flags: PageTableFlags, // 映射这个区域时使用的页表标志位
+    pub lazy: bool,            // 是否在第一次访问时才分配物理帧
+}
+
+impl VmArea {
+    /// 区域的结束地址 (不含)
+    pub fn end(&self) -> VirtAddr {
+        self.start + self.size
+    }
+
+    /// 判断给定地址是否位于区域内
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end()
+    }
+
+    /// 区域包含的所有页
+    pub fn pages(&self) -> PageRange {
+        let start = Page::containing_address(self.start);
+        let end = Page::containing_address(self.end());
+        Page::range(start, end)
+    }
+}
+
+/// 虚拟内存管理操作失败的原因
+#[derive(Debug)]
+pub enum VmError {
+    OutOfVirtualSpace,           // 没有足够大的空闲虚拟地址区间
+    TooManyAreas,                // 区域表已满
+    AreaNotFound,                // 给定地址不是任何区域的起始地址
+    PageNotMapped,               // 给定页面没有被映射
+    NotUserAddress,              // 给定页面属于和内核共享的部分
+    TooManySharedFrames,         // 写时复制的引用计数表已满
+    Map(MapToError<Size4KiB>),   // 映射页面失败
+    Unmap(UnmapError),           // 取消映射失败
+    FlagUpdate(FlagUpdateError), // 修改页表标志位失败
+}
+
+impl From<MapToError<Size4KiB>> for VmError {
+    fn from(err: MapToError<Size4KiB>) -> Self {
+        VmError::Map(err)
+    }
+}
+
+impl From<UnmapError> for VmError {
+    fn from(err: UnmapError) -> Self {
+        VmError::Unmap(err)
+    }
+}
+
+impl From<FlagUpdateError> for VmError {
+    fn from(err: FlagUpdateError) -> Self {
+        VmError::FlagUpdate(err)
+    }
+}
+
+/// 管理一段虚拟地址区间，把它划分成互不重叠的区域
+pub struct VirtualMemoryManager {
+    areas: [Option<VmArea>; MAX_AREAS], // 前 `len` 项按起始地址排序
+    len: usize,                         // 当前区域数量
+    start: VirtAddr,                    // 管理区间的起始地址
+    end: VirtAddr,                      // 管理区间的结束地址 (不含)
+}
+
+impl VirtualMemoryManager {
+    /// 创建一个管理 `[start, end)` 的空管理器
+    pub const fn new(start: VirtAddr, end: VirtAddr) -> Self {
+        const EMPTY: Option<VmArea> = None;
+        VirtualMemoryManager {
+            areas: [EMPTY; MAX_AREAS],
+            len: 0,
+            start,
+            end,
+        }
+    }
+
+    /// 预留一段至少 `size` 字节的虚拟地址区间，但不映射任何页面
+    ///
+    /// 使用首次适应，返回新区域
+    pub fn reserve(
+        &mut self,
+        size: u64,
+        kind: AreaKind,
+        flags: PageTableFlags,
+    ) -> Result<VmArea, VmError> {
+        self.insert(size, kind, flags, false)
+    }
+
+    /// 预留一段按需分配的虚拟地址区间
+    ///
+    /// 区域内的页面在第一次被访问时由页错误处理函数分配、清零并映射，
+    /// 所以预留很大的缓冲区也不会立即消耗物理内存
+    pub fn reserve_lazy(
+        &mut self,
+        size: u64,
+        kind: AreaKind,
+        flags: PageTableFlags,
+    ) -> Result<VmArea, VmError> {
+        self.insert(size, kind, flags, true)
+    }
+
+    /// 找到足够大的空隙并插入一个新区域
+    fn insert(
+        &mut self,
+        size: u64,
+        kind: AreaKind,
+        flags: PageTableFlags,
+        lazy: bool,
+    ) -> Result<VmArea, VmError> {
+        if self.len == MAX_AREAS {
+            return Err(VmError::TooManyAreas);
+        }
+        let size = (size.max(1) + PAGE_SIZE - 1) & !(PAGE_SIZE - 1); // 向上对齐到整页
+        // 从前往后找第一个足够大的空隙
+        let mut cursor = self.start;
+        let mut index = self.len;
+        for (i, area) in self.iter().enumerate() {
+            if area.start - cursor >= size {
+                index = i;
+                break;
+            }
+            cursor = area.end();
+        }
+        if index == self.len && self.end - cursor < size {
+            return Err(VmError::OutOfVirtualSpace);
+        }
+        let area = VmArea {
+            start: cursor,
+            size,
+            kind,
+            flags,
+            lazy,
+        };
+        // 插入并保持有序
+        self.areas[index..=self.len].rotate_right(1);
+        self.areas[index] = Some(area);
+        self.len += 1;
+        Ok(area)
+    }
+
+    /// 释放以 `start` 开头的区域，不会取消映射它的页面
+    pub fn release(&mut self, start: VirtAddr) -> Result<VmArea, VmError> {
+        let index = self.index_of(start)?;
+        let area = self.areas[index].take().unwrap();
+        self.areas[index..self.len].rotate_left(1);
+        self.len -= 1;
+        Ok(area)
+    }
+
+    /// 查找包含给定地址的区域
+    pub fn find(&self, addr: VirtAddr) -> Option<VmArea> {
+        self.iter().find(|area| area.contains(addr))
+    }
+
+    /// 按地址顺序遍历所有区域
+    pub fn iter(&self) -> impl Iterator<Item = VmArea> + '_ {
+        self.areas[..self.len].iter().map(|area| area.unwrap())
+    }
+
+    /// 为以 `start` 开头的区域的每一页分配一个物理帧，并按区域的标志位映射
+    ///
+    /// 出错时已经映射的页面保持不变，可以用 `unmap` 回收
+    pub fn map(
+        &self,
+        start: VirtAddr,
+        mapper: &mut impl Mapper<Size4KiB>,
+        frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>),
+    ) -> Result<(), VmError> {
+        let area = self.areas[self.index_of(start)?].unwrap();
+        let owner = area.kind.frame_owner();
+        map_pages(area.pages(), area.flags, owner, mapper, frame_allocator)
+    }
+
+    /// 取消映射以 `start` 开头的区域的所有页面，并把物理帧还给帧分配器
+    ///
+    /// 没有映射的页面会被跳过。这个函数是不安全的，
+    /// 因为调用者必须保证这些页面不再被使用，并且它们的帧属于 `frame_deallocator`
+    pub unsafe fn unmap(
+        &self,
+        start: VirtAddr,
+        mapper: &mut impl Mapper<Size4KiB>,
+        frame_deallocator: &mut impl FrameDeallocator<Size4KiB>,
+    ) -> Result<(), VmError> {
+        let area = self.areas[self.index_of(start)?].unwrap();
+        for page in area.pages() {
+            match mapper.unmap(page) {
+                Ok((frame, flush)) => {
+                    flush.flush();
+                    unsafe { frame_deallocator.deallocate_frame(frame) };
+                    accounting::uncharge(area.kind.frame_owner(), 1);
+                }
+                Err(UnmapError::PageNotMapped) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// 修改以 `start` 开头的区域的保护属性，已经映射的页面会立即生效
+    ///
+    /// 这个函数是不安全的，因为去掉权限后，仍在使用这些页面的代码可能会触发页错误
+    pub unsafe fn protect(
+        &mut self,
+        start: VirtAddr,
+        flags: PageTableFlags,
+        mapper: &mut impl Mapper<Size4KiB>,
+    ) -> Result<(), VmError> {
+        let index = self.index_of(start)?;
+        let area = self.areas[index].as_mut().unwrap();
+        for page in area.pages() {
+            match unsafe { mapper.update_flags(page, flags) } {
+                Ok(flush) => flush.flush(),
+                Err(FlagUpdateError::PageNotMapped) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        area.flags = flags;
+        Ok(())
+    }
+
+    /// 返回以 `start` 开头的区域在区域表中的下标
+    fn index_of(&self, start: VirtAddr) -> Result<usize, VmError> {
+        self.iter()
+            .position(|area| area.start == start)
+            .ok_or(VmError::AreaNotFound)
+    }
+}
+
+/// 为 `pages` 中的每一页分配一个物理帧，并按 `flags` 映射
+///
+/// 数据帧记到 `owner` 名下，新建的页表记到 `FrameOwner::PageTable` 名下。
+/// 出错时已经映射的页面保持不变，出错的那一页的帧会被还给帧分配器
+pub fn map_pages(
+    pages: PageRange,
+    flags: PageTableFlags,
+    owner: FrameOwner,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>),
+) -> Result<(), VmError> {
+    for page in pages {
+        let frame = frame_allocator
+            .allocate_frame() // 从帧分配器中分配一个物理帧
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        // 映射到页表中并刷新 TLB
+        let mut page_tables = Accounted::new(&mut *frame_allocator, FrameOwner::PageTable);
+        match unsafe { mapper.map_to(page, frame, flags, &mut page_tables) } {
+            Ok(flush) => flush.flush(),
+            Err(err) => {
+                unsafe { frame_allocator.deallocate_frame(frame) };
+                return Err(err.into());
+            }
+        }
+        // 映射成功后才记账
+        accounting::charge(owner, 1);
+    }
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use super::accounting::FrameOwner;
+use super::vma::{self, AreaKind, KERNEL_SPACE, VmError};
+use super::wx;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::Page;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// 分配一段至少 `size` 字节、虚拟地址连续的内核缓冲区
+///
+/// 每一页单独从帧分配器中分配物理帧，物理上不需要连续，所以物理内存
+/// 碎片化时也能分配几 MiB 的缓冲区。缓冲区之后保留一个不映射的保护页，
+/// 越界访问会触发页错误。缓冲区的内容没有初始化，返回它的起始地址
+pub fn vmalloc(size: usize) -> Result<VirtAddr, VmError> {
+    let pages = (size.max(1) as u64).div_ceil(PAGE_SIZE);
+    let area =
+        KERNEL_SPACE
+            .lock()
+            .reserve((pages + 1) * PAGE_SIZE, AreaKind::Vmalloc, wx::DATA_FLAGS)?;
+    let first = Page::containing_address(area.start);
+    let mapped = super::with_kernel_memory(|mapper, frame_allocator| {
+        vma::map_pages(
+            Page::range(first, first + pages),
+            area.flags,
+            FrameOwner::Other,
+            mapper,
+            frame_allocator,
+        )
+    })
+    .expect("memory::install must be called before vmalloc");
+    if let Err(err) = mapped {
+        unsafe { vfree(area.start)? };
+        return Err(err);
+    }
+    Ok(area.start)
+}
+
+/// 释放 `vmalloc` 分配的缓冲区，把它的物理帧还给帧分配器
+///
+/// 这个函数是不安全的，因为调用者必须保证 `addr` 是 `vmalloc` 返回的地址，
+/// 并且缓冲区不再被使用
+pub unsafe fn vfree(addr: VirtAddr) -> Result<(), VmError> {
+    let mut kernel_space = KERNEL_SPACE.lock();
+    match kernel_space.find(addr) {
+        Some(area) if area.kind == AreaKind::Vmalloc && area.start == addr => {}
+        _ => return Err(VmError::AreaNotFound),
+    }
+    super::with_kernel_memory(|mapper, frame_allocator| unsafe {
+        kernel_space.unmap(addr, mapper, frame_allocator)
+    })
+    .expect("memory::install must be called before vfree")?;
+    kernel_space.release(addr)?;
+    Ok(())
+}
+
+/// 返回 `vmalloc` 分配的缓冲区的可用大小 (字节)，不包括保护页
+pub fn vsize(addr: VirtAddr) -> Option<usize> {
+    let area = KERNEL_SPACE.lock().find(addr)?;
+    (area.kind == AreaKind::Vmalloc && area.start == addr).then(|| (area.size - PAGE_SIZE) as usize)
+}
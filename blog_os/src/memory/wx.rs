@@ -0,0 +1,165 @@
+use super::dump;
+use x86_64::VirtAddr;
+use x86_64::instructions::tlb;
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::structures::paging::mapper::{MappedFrame, TranslateResult};
+use x86_64::structures::paging::{
+    Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, Size4KiB, Translate,
+};
+
+unsafe extern "C" {
+    /// 链接器定义的符号，位于内核 ELF 头部。头部和程序头表在第一个可加载段中，
+    /// 被引导程序和其他段一起映射
+    static __ehdr_start: u8;
+}
+
+/// 程序头表中可加载段的类型
+const PT_LOAD: u32 = 1;
+/// 段标志位：可执行
+const PF_X: u32 = 1;
+
+/// 内核映像中的一个可加载段
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    start: VirtAddr,  // 段的起始虚拟地址
+    end: VirtAddr,    // 段的结束虚拟地址 (不含)
+    executable: bool, // 是否是代码段
+}
+
+impl Segment {
+    /// 段占用的第一页和最后一页
+    fn page_bounds(&self) -> (Page, Page) {
+        (
+            Page::<Size4KiB>::containing_address(self.start),
+            Page::<Size4KiB>::containing_address(self.end - 1u64),
+        )
+    }
+}
+
+/// 内核数据页面 (堆、栈、缓冲区) 使用的标志位：可写但不可执行
+pub const DATA_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::NO_EXECUTE);
+
+/// 打开 EFER.NXE，让页表项中的 NO_EXECUTE 位生效
+///
+/// 没有打开 NXE 时 NO_EXECUTE 是保留位，设置了它的页表项会触发页错误，
+/// 所以必须在映射任何带 NO_EXECUTE 的页面之前调用
+pub fn enable_nxe() {
+    unsafe { Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE)) };
+}
+
+/// 给当前地址空间中所有可写的页面加上 NO_EXECUTE，保证没有页面既可写又可执行，
+/// 再按内核的程序头表给代码段以外的内核映像加上 NO_EXECUTE
+///
+/// 数据段、bss、启动栈、物理内存映射和堆都是可写的，会变成不可执行；
+/// rodata 只读也不可执行，最后只有 .text 所在的代码段可以执行。
+/// 只修改最后一级页表项，上级页表项的 NO_EXECUTE 会作用到整棵子树，不能随便设置。
+/// 返回被修改的页表项数量
+pub fn enforce(mapper: &mut OffsetPageTable) -> usize {
+    let phys_offset = mapper.phys_offset();
+    let mut fixed = enforce_table(mapper.level_4_table(), phys_offset, 4);
+    fixed += enforce_kernel_segments(mapper);
+    tlb::flush_all();
+    fixed
+}
+
+/// 读取内核自己的程序头表，返回所有可加载段
+fn kernel_segments() -> impl Iterator<Item = Segment> {
+    let ehdr = &raw const __ehdr_start;
+    // ELF64 头部中程序头表的偏移、每一项的大小和项数
+    let (phoff, phentsize, phnum) = unsafe {
+        (
+            ehdr.add(0x20).cast::<u64>().read_unaligned() as usize,
+            ehdr.add(0x36).cast::<u16>().read_unaligned() as usize,
+            ehdr.add(0x38).cast::<u16>().read_unaligned() as usize,
+        )
+    };
+    (0..phnum).filter_map(move |index| {
+        let phdr = unsafe { ehdr.add(phoff + index * phentsize) };
+        let (p_type, p_flags, p_vaddr, p_memsz) = unsafe {
+            (
+                phdr.cast::<u32>().read_unaligned(),
+                phdr.add(0x04).cast::<u32>().read_unaligned(),
+                phdr.add(0x10).cast::<u64>().read_unaligned(),
+                phdr.add(0x28).cast::<u64>().read_unaligned(),
+            )
+        };
+        (p_type == PT_LOAD && p_memsz > 0).then(|| Segment {
+            start: VirtAddr::new(p_vaddr),
+            end: VirtAddr::new(p_vaddr + p_memsz),
+            executable: p_flags & PF_X != 0,
+        })
+    })
+}
+
+/// 给内核映像中不可执行的段 (rodata、data、bss) 的每一页加上 NO_EXECUTE
+///
+/// 和代码段共用的页保持可执行。返回被修改的页表项数量
+fn enforce_kernel_segments(mapper: &mut OffsetPageTable) -> usize {
+    let mut fixed = 0;
+    for segment in kernel_segments().filter(|segment| !segment.executable) {
+        let (first, last) = segment.page_bounds();
+        for page in Page::range_inclusive(first, last) {
+            let shares_code = kernel_segments()
+                .filter(|other| other.executable)
+                .map(|code| code.page_bounds())
+                .any(|(code_first, code_last)| code_first <= page && page <= code_last);
+            if shares_code {
+                continue;
+            }
+            let TranslateResult::Mapped {
+                frame: MappedFrame::Size4KiB(_),
+                flags,
+                ..
+            } = mapper.translate(page.start_address())
+            else {
+                continue; // 引导程序用 4KiB 页面映射内核，其他情况不处理
+            };
+            if flags.contains(PageTableFlags::NO_EXECUTE) {
+                continue;
+            }
+            // 之后会统一刷新 TLB
+            if unsafe { mapper.update_flags(page, flags | PageTableFlags::NO_EXECUTE) }.is_ok() {
+                fixed += 1;
+            }
+        }
+    }
+    fixed
+}
+
+/// 统计当前地址空间中既可写又可执行的映射区间数量
+pub fn violations(mapper: &mut OffsetPageTable) -> usize {
+    let mut count = 0;
+    dump::for_each_range(mapper, |range| {
+        if range.flags.contains(PageTableFlags::WRITABLE)
+            && !range.flags.contains(PageTableFlags::NO_EXECUTE)
+        {
+            count += 1;
+        }
+    });
+    count
+}
+
+/// 递归处理 `level` 级页表，返回被修改的页表项数量
+fn enforce_table(table: &mut PageTable, phys_offset: VirtAddr, level: u8) -> usize {
+    let mut fixed = 0;
+    for entry in table.iter_mut() {
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        let is_leaf = level == 1 || (level <= 3 && flags.contains(PageTableFlags::HUGE_PAGE));
+        if !is_leaf {
+            let virt = phys_offset + entry.addr().as_u64();
+            let next: &mut PageTable = unsafe { &mut *virt.as_mut_ptr() };
+            fixed += enforce_table(next, phys_offset, level - 1);
+        } else if flags.contains(PageTableFlags::WRITABLE)
+            && !flags.contains(PageTableFlags::NO_EXECUTE)
+        {
+            entry.set_flags(flags | PageTableFlags::NO_EXECUTE);
+            fixed += 1;
+        }
+    }
+    fixed
+}
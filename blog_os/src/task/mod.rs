@@ -27,7 +27,25 @@ impl Task {
     // 由于 Future trait 的 poll 方法期望被 Pin<&mut T> 类型调用，
     // 我们使用 Pin::as_mut 方法先转换 Pin<Box<T>> 类型的 self.future 字段
     fn poll(&mut self, context: &mut Context) -> Poll<()> {
-        self.future.as_mut().poll(context)
+        // 记录正在运行的任务，分配追踪会把它写进记录里
+        CURRENT_TASK.store(self.id.0, Ordering::Relaxed);
+        let result = self.future.as_mut().poll(context);
+        CURRENT_TASK.store(NO_TASK, Ordering::Relaxed);
+        result
+    }
+}
+
+/// 没有任务在运行时 `CURRENT_TASK` 的值
+const NO_TASK: u64 = u64::MAX;
+
+/// 正在被执行器轮询的任务 ID
+static CURRENT_TASK: AtomicU64 = AtomicU64::new(NO_TASK);
+
+/// 返回正在被执行器轮询的任务 ID，不在任务中时返回 None
+pub fn current_task_id() -> Option<u64> {
+    match CURRENT_TASK.load(Ordering::Relaxed) {
+        NO_TASK => None,
+        id => Some(id),
     }
 }
 
@@ -0,0 +1,154 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use blog_os::allocator;
+use blog_os::memory::{
+    self,
+    address_space::{self, AddressSpace},
+    bitmap::BitmapFrameAllocator,
+    wx,
+};
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, Mapper, Page, Translate};
+
+entry_point!(main);
+
+// 用户部分的测试地址，位于第 224 个 4 级页表项
+const USER_ADDR: u64 = 0x_7000_0000_0000;
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::install(mapper, frame_allocator);
+    allocator::init_heap().expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+fn free_frames() -> usize {
+    memory::with_kernel_memory(|_, frame_allocator| frame_allocator.free_frames()).unwrap()
+}
+
+fn user_page(space: &AddressSpace) -> Page {
+    let page = Page::containing_address(VirtAddr::new(USER_ADDR));
+    assert!(space.is_user_page(page));
+    page
+}
+
+#[test_case]
+fn kernel_keeps_running_after_switch() {
+    let kernel_frame = Cr3::read().0;
+    let space = AddressSpace::new().unwrap();
+    unsafe { space.activate() };
+    assert!(space.is_active());
+    // 内核的代码、栈和堆都在共享的项中，切换后仍然可以使用
+    let boxed = alloc::boxed::Box::new(42);
+    assert_eq!(*boxed, 42);
+    address_space::activate_kernel();
+    assert_eq!(Cr3::read().0, kernel_frame);
+}
+
+#[test_case]
+fn user_pages_are_isolated() {
+    let mut first = AddressSpace::new().unwrap();
+    let mut second = AddressSpace::new().unwrap();
+    let page = user_page(&first);
+    let ptr = page.start_address().as_mut_ptr::<u64>();
+    let flags = wx::DATA_FLAGS;
+    first.map_user(page, flags).unwrap();
+    // 只映射在第一个地址空间里
+    assert!(
+        first
+            .mapper()
+            .translate_addr(page.start_address())
+            .is_some()
+    );
+    assert!(
+        second
+            .mapper()
+            .translate_addr(page.start_address())
+            .is_none()
+    );
+    second.map_user(page, flags).unwrap();
+
+    unsafe {
+        first.activate();
+        ptr.write_volatile(1);
+        second.activate();
+        assert_eq!(ptr.read_volatile(), 0); // 新页面已被清零
+        ptr.write_volatile(2);
+        first.activate();
+        assert_eq!(ptr.read_volatile(), 1);
+    }
+    address_space::activate_kernel();
+    unsafe { first.unmap_user(page).unwrap() };
+    assert!(
+        first
+            .mapper()
+            .translate_addr(page.start_address())
+            .is_none()
+    );
+}
+
+#[test_case]
+fn drop_frees_all_frames() {
+    let free_before = free_frames();
+    let mut space = AddressSpace::new().unwrap();
+    let page = user_page(&space);
+    for i in 0..8 {
+        space.map_user(page + i, wx::DATA_FLAGS).unwrap();
+    }
+    unsafe { space.activate() };
+    drop(space); // 正在使用的地址空间被释放时会切换回内核页表
+    assert_eq!(free_frames(), free_before);
+}
+
+// 放在最后：内核在测试地址所在的 4 级页表项中留下了页表，之后创建的地址空间会共享这一项
+#[test_case]
+fn kernel_slot_added_later_stays_owned() {
+    let mut space = AddressSpace::new().unwrap();
+    let page = user_page(&space);
+    let free_before = free_frames();
+    space.map_user(page, wx::DATA_FLAGS).unwrap();
+    let used = free_before - free_frames();
+
+    // 内核在同一个 4 级页表项中映射一个页面，这一项在内核页表中不再是空的
+    let kernel_page = page + 512 * 512; // 相隔 1 GiB，不共用下级页表
+    memory::with_kernel_memory(|mapper, frame_allocator| {
+        let frame = frame_allocator.allocate_frame().unwrap();
+        unsafe { mapper.map_to(kernel_page, frame, wx::DATA_FLAGS, frame_allocator) }
+            .unwrap()
+            .flush();
+    })
+    .unwrap();
+    assert!(space.is_user_page(page));
+
+    // 释放时这一项仍然属于地址空间自己，用户页面、页表和 4 级页表都被释放
+    let free_after_kernel_map = free_frames();
+    drop(space);
+    assert_eq!(free_frames(), free_after_kernel_map + used + 1);
+
+    memory::with_kernel_memory(|mapper, frame_allocator| {
+        let (frame, flush) = mapper.unmap(kernel_page).unwrap();
+        flush.flush();
+        unsafe { frame_allocator.deallocate_frame(frame) };
+    })
+    .unwrap();
+}
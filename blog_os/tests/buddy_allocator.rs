@@ -0,0 +1,137 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::memory::{
+    self, CONTIGUOUS_POOL_FRAMES, MappingSize,
+    bitmap::BitmapFrameAllocator,
+    buddy::{BuddyFrameAllocator, MAX_ORDER},
+    vma::{AreaKind, KERNEL_SPACE},
+    wx,
+};
+use bootloader::{BootInfo, entry_point};
+use conquer_once::spin::OnceCell;
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, Page, PageSize, PhysFrame, Size2MiB, Size4KiB,
+};
+
+entry_point!(main);
+
+static PHYS_MEM_OFFSET: OnceCell<VirtAddr> = OnceCell::uninit();
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    PHYS_MEM_OFFSET.init_once(|| VirtAddr::new(boot_info.physical_memory_offset));
+    let phys_mem_offset = *PHYS_MEM_OFFSET.get().unwrap();
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::install(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+/// 记录每个阶上空闲块的数量
+fn snapshot(buddy: &BuddyFrameAllocator) -> [usize; MAX_ORDER + 1] {
+    let mut counts = [0; MAX_ORDER + 1];
+    for (order, count) in counts.iter_mut().enumerate() {
+        *count = buddy.free_blocks(order);
+    }
+    counts
+}
+
+#[test_case]
+fn pool_is_installed() {
+    memory::with_contiguous_frames(|buddy| {
+        assert_eq!(buddy.free_frames(), CONTIGUOUS_POOL_FRAMES);
+        assert_eq!(buddy.free_blocks(MAX_ORDER), 1);
+    })
+    .unwrap();
+}
+
+#[test_case]
+fn blocks_are_aligned() {
+    memory::with_contiguous_frames(|buddy| {
+        for order in 0..=MAX_ORDER {
+            let addr = buddy.allocate(order).unwrap();
+            assert!(addr.is_aligned(Size4KiB::SIZE << order));
+            unsafe { buddy.deallocate(addr, order) };
+        }
+    })
+    .unwrap();
+}
+
+#[test_case]
+fn freed_blocks_merge_with_buddies() {
+    memory::with_contiguous_frames(|buddy| {
+        let before = snapshot(buddy);
+        let free_before = buddy.free_frames();
+
+        // 分配一个 8 帧的块，然后逐帧释放，它们应该重新合并
+        let addr = buddy.allocate(3).unwrap();
+        assert_eq!(buddy.free_frames(), free_before - 8);
+        for i in 0..8 {
+            unsafe { buddy.deallocate(addr + i * Size4KiB::SIZE, 0) };
+        }
+        assert_eq!(buddy.free_frames(), free_before);
+        assert_eq!(snapshot(buddy), before);
+    })
+    .unwrap();
+}
+
+#[test_case]
+fn huge_frames() {
+    memory::with_contiguous_frames(|buddy| {
+        let free_before = buddy.free_frames();
+        let frame: PhysFrame<Size2MiB> = buddy.allocate_frame().unwrap();
+        assert!(frame.start_address().is_aligned(Size2MiB::SIZE));
+        assert_eq!(buddy.free_frames(), free_before - 512);
+        unsafe { buddy.deallocate_frame(frame) };
+        assert_eq!(buddy.free_frames(), free_before);
+    })
+    .unwrap();
+}
+
+#[test_case]
+fn map_huge_page_from_pool() {
+    let offset = *PHYS_MEM_OFFSET.get().unwrap();
+    let frame: PhysFrame<Size2MiB> =
+        memory::with_contiguous_frames(|buddy| buddy.allocate_frame().unwrap()).unwrap();
+    // 预留两个大页大小的区域，保证其中有一个按 2MiB 对齐的大页
+    let area = KERNEL_SPACE
+        .lock()
+        .reserve(2 * Size2MiB::SIZE, AreaKind::Other, wx::DATA_FLAGS)
+        .unwrap();
+    let page = Page::<Size2MiB>::containing_address(area.start.align_up(Size2MiB::SIZE));
+    memory::with_kernel_memory(|mapper, frame_allocator| {
+        unsafe { mapper.map_to(page, frame, wx::DATA_FLAGS, frame_allocator) }
+            .unwrap()
+            .flush();
+    })
+    .unwrap();
+
+    let addr = page.start_address() + 0x1234u64;
+    unsafe { addr.as_mut_ptr::<u64>().write_volatile(0x5a5a) };
+    let translation = unsafe { memory::translate(addr, offset) }.unwrap();
+    assert_eq!(translation.size, MappingSize::Size2MiB);
+    assert_eq!(translation.phys_addr, frame.start_address() + 0x1234u64);
+
+    memory::with_kernel_memory(|mapper, _| {
+        let (unmapped, flush) = Mapper::<Size2MiB>::unmap(mapper, page).unwrap();
+        flush.flush();
+        assert_eq!(unmapped, frame);
+    })
+    .unwrap();
+    KERNEL_SPACE.lock().release(area.start).unwrap();
+    memory::with_contiguous_frames(|buddy| unsafe { buddy.deallocate_frame(frame) }).unwrap();
+}
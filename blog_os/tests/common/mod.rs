@@ -0,0 +1,33 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+
+/// 测试堆的默认大小
+pub const ARENA_SIZE: usize = 64 * 1024;
+
+/// 测试用的堆，和全局分配器无关，也不会扩容
+///
+/// 按页对齐，这样固定大小块分配器可以按页切分它
+#[repr(align(4096))]
+pub struct Arena<const SIZE: usize = ARENA_SIZE>(UnsafeCell<[u8; SIZE]>);
+
+// 内存只通过交给分配器的起始地址访问，由分配器的锁保护
+unsafe impl<const SIZE: usize> Sync for Arena<SIZE> {}
+
+impl<const SIZE: usize> Arena<SIZE> {
+    pub const fn new() -> Self {
+        Arena(UnsafeCell::new([0; SIZE]))
+    }
+
+    /// 起始地址，用于初始化分配器
+    pub fn start(&self) -> usize {
+        self.0.get() as usize
+    }
+}
+
+/// 从 `allocator` 分配，分配失败或者没有按要求对齐时让测试失败
+pub fn alloc(allocator: &impl GlobalAlloc, layout: Layout) -> *mut u8 {
+    let ptr = unsafe { allocator.alloc(layout) };
+    assert!(!ptr.is_null(), "allocation of {:?} failed", layout);
+    assert_eq!(ptr as usize % layout.align(), 0);
+    ptr
+}
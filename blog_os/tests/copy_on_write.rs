@@ -0,0 +1,141 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::memory::{
+    self, FRAME_ALLOCATOR,
+    accounting::FrameOwner,
+    bitmap::BitmapFrameAllocator,
+    cow,
+    vma::{self, AreaKind, KERNEL_SPACE},
+};
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use x86_64::structures::paging::{Page, PageTableFlags, Translate};
+use x86_64::{PhysAddr, VirtAddr};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::install(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+fn free_frames() -> usize {
+    FRAME_ALLOCATOR.lock().as_ref().unwrap().free_frames()
+}
+
+fn phys_addr(page: Page) -> PhysAddr {
+    memory::with_kernel_memory(|mapper, _| mapper.translate_addr(page.start_address()))
+        .unwrap()
+        .unwrap()
+}
+
+/// 预留两页虚拟地址，只映射第一页并写入数据
+fn setup() -> (VirtAddr, Page, Page) {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let area = KERNEL_SPACE
+        .lock()
+        .reserve(2 * 4096, AreaKind::Other, flags)
+        .unwrap();
+    let src = Page::containing_address(area.start);
+    let dst = src + 1;
+    memory::with_kernel_memory(|mapper, frame_allocator| {
+        vma::map_pages(
+            Page::range(src, dst),
+            flags,
+            FrameOwner::Other,
+            mapper,
+            frame_allocator,
+        )
+    })
+    .unwrap()
+    .unwrap();
+    unsafe { src.start_address().as_mut_ptr::<u64>().write_volatile(42) };
+    (area.start, src, dst)
+}
+
+fn teardown(start: VirtAddr, src: Page, dst: Page) {
+    unsafe {
+        cow::unmap(src).unwrap();
+        cow::unmap(dst).unwrap();
+    }
+    KERNEL_SPACE.lock().release(start).unwrap();
+}
+
+#[test_case]
+fn shared_page_reads_same_frame() {
+    let (start, src, dst) = setup();
+    cow::share(src, dst).unwrap();
+    assert_eq!(phys_addr(src), phys_addr(dst));
+    assert_eq!(
+        unsafe { dst.start_address().as_ptr::<u64>().read_volatile() },
+        42
+    );
+    teardown(start, src, dst);
+}
+
+#[test_case]
+fn write_copies_shared_frame() {
+    let (start, src, dst) = setup();
+    cow::share(src, dst).unwrap();
+    let shared = phys_addr(src);
+
+    // 写入 dst 会复制出一个私有的帧，src 不受影响
+    let dst_ptr = dst.start_address().as_mut_ptr::<u64>();
+    unsafe { dst_ptr.write_volatile(7) };
+    assert_ne!(phys_addr(dst), shared);
+    assert_eq!(unsafe { dst_ptr.read_volatile() }, 7);
+    assert_eq!(
+        unsafe { src.start_address().as_ptr::<u64>().read_volatile() },
+        42
+    );
+
+    // src 现在是唯一的使用者，写入时不再复制
+    unsafe { src.start_address().as_mut_ptr::<u64>().write_volatile(43) };
+    assert_eq!(phys_addr(src), shared);
+    teardown(start, src, dst);
+}
+
+#[test_case]
+fn last_unmap_frees_frame() {
+    let (start, src, dst) = setup();
+    let free_before = free_frames();
+    cow::share(src, dst).unwrap();
+    unsafe { cow::unmap(dst).unwrap() };
+    // 还有一个使用者，帧不会被释放
+    assert_eq!(free_frames(), free_before);
+    unsafe { cow::unmap(src).unwrap() };
+    assert_eq!(free_frames(), free_before + 1);
+    KERNEL_SPACE.lock().release(start).unwrap();
+}
+
+#[test_case]
+fn unmap_after_copy_frees_original_frame() {
+    let (start, src, dst) = setup();
+    let free_before = free_frames();
+    cow::share(src, dst).unwrap();
+    // 写入 dst 复制出一个私有的帧，src 成为原来的帧的唯一使用者
+    unsafe { dst.start_address().as_mut_ptr::<u64>().write_volatile(7) };
+    assert_eq!(free_frames(), free_before - 1);
+    // src 没有写入过就取消映射，原来的帧也要被释放
+    unsafe { cow::unmap(src).unwrap() };
+    assert_eq!(free_frames(), free_before);
+    unsafe { cow::unmap(dst).unwrap() };
+    assert_eq!(free_frames(), free_before + 1);
+    KERNEL_SPACE.lock().release(start).unwrap();
+}
@@ -0,0 +1,80 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::memory::{
+    self, FRAME_ALLOCATOR,
+    bitmap::BitmapFrameAllocator,
+    vma::{AreaKind, KERNEL_SPACE},
+};
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::PageTableFlags;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::install(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+fn free_frames() -> usize {
+    FRAME_ALLOCATOR.lock().as_ref().unwrap().free_frames()
+}
+
+#[test_case]
+fn lazy_area_is_backed_on_access() {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let size = 64 * 1024 * 1024;
+    let area = KERNEL_SPACE
+        .lock()
+        .reserve_lazy(size, AreaKind::Other, flags)
+        .unwrap();
+    let free_before = free_frames();
+
+    // 每隔 1 MiB 写一次，只有被访问的页面会分配物理帧
+    let base = area.start.as_mut_ptr::<u64>();
+    for i in 0..64 {
+        let ptr = unsafe { base.add(i * 1024 * 1024 / 8) };
+        assert_eq!(unsafe { ptr.read_volatile() }, 0); // 新页面已被清零
+        unsafe { ptr.write_volatile(i as u64) };
+    }
+    for i in 0..64 {
+        let ptr = unsafe { base.add(i * 1024 * 1024 / 8) };
+        assert_eq!(unsafe { ptr.read_volatile() }, i as u64);
+    }
+    // 除了 64 个数据页，每个被访问的 2 MiB 区域还需要一个 L1 页表，
+    // 再加上最多两个 L2 和两个 L3 页表 (区域可能跨过 1 GiB 或 512 GiB 边界)。
+    // 之前的测试可能已经建好了其中一些页表，所以只检查上限
+    let first_region = area.start.as_u64() >> 21;
+    let last_region = (area.start.as_u64() + 63 * 1024 * 1024) >> 21;
+    let l1_tables = (last_region - first_region + 1) as usize;
+    let used = free_before - free_frames();
+    assert!(
+        used >= 64 && used <= 64 + l1_tables + 4,
+        "{} frames used for 64 pages in {} 2 MiB regions",
+        used,
+        l1_tables
+    );
+
+    memory::with_kernel_memory(|mapper, frame_allocator| {
+        let kernel_space = KERNEL_SPACE.lock();
+        unsafe { kernel_space.unmap(area.start, mapper, frame_allocator) }.unwrap();
+    });
+    KERNEL_SPACE.lock().release(area.start).unwrap();
+}
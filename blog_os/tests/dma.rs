@@ -0,0 +1,79 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::memory::{
+    self, accounting,
+    bitmap::BitmapFrameAllocator,
+    dma::{DMA32_LIMIT, DmaBuffer, ISA_DMA_LIMIT},
+};
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::Translate;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::install(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+/// 帧分配器和连续内存池中一共空闲的帧
+fn free_frames() -> usize {
+    accounting::stats().unwrap().free_frames
+}
+
+#[test_case]
+fn isa_buffer_respects_limit_and_alignment() {
+    let buffer = DmaBuffer::new(3 * 4096, 64 * 1024, ISA_DMA_LIMIT).unwrap();
+    let phys = buffer.phys_addr().as_u64();
+    assert_eq!(phys % (64 * 1024), 0);
+    assert!(phys + 3 * 4096 <= ISA_DMA_LIMIT);
+    assert_eq!(buffer.len(), 3 * 4096);
+    assert!(buffer.as_slice().iter().all(|&b| b == 0));
+}
+
+#[test_case]
+fn buffer_is_physically_contiguous() {
+    let mut buffer = DmaBuffer::new(8 * 4096, 4096, DMA32_LIMIT).unwrap();
+    let phys = buffer.phys_addr();
+    for i in 0..8u64 {
+        let virt = buffer.virt_addr() + i * 4096;
+        let translated = memory::with_kernel_memory(|mapper, _| mapper.translate_addr(virt))
+            .unwrap()
+            .unwrap();
+        assert_eq!(translated, phys + i * 4096);
+    }
+    buffer.as_mut_slice().fill(0x5a);
+    assert_eq!(buffer.as_slice()[8 * 4096 - 1], 0x5a);
+}
+
+#[test_case]
+fn drop_returns_frames() {
+    let free_before = free_frames();
+    let buffer = DmaBuffer::new(5000, 4096, DMA32_LIMIT).unwrap();
+    assert_eq!(free_frames(), free_before - 2);
+    drop(buffer);
+    assert_eq!(free_frames(), free_before);
+}
+
+#[test_case]
+fn impossible_request_fails() {
+    // 16 MiB 以下不可能有 32 MiB 的连续内存
+    assert!(DmaBuffer::new(32 * 1024 * 1024, 4096, ISA_DMA_LIMIT).is_none());
+}
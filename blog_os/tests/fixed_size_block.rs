@@ -0,0 +1,126 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+mod common;
+
+use blog_os::allocator::Locked;
+use blog_os::allocator::fixed_size_block::{BLOCK_SIZES, FixedSizeBlockAllocator};
+use blog_os::allocator::stats::{HeapStatistics, SizeClassStats};
+use bootloader::{BootInfo, entry_point};
+use common::{ARENA_SIZE, Arena, alloc};
+use core::alloc::{GlobalAlloc, Layout};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+static ARENA: Arena = Arena::new();
+static HEAP: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    unsafe { HEAP.lock().init(ARENA.start(), ARENA_SIZE) };
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+const BLOCK: usize = 64;
+const BLOCKS_PER_PAGE: usize = 4096 / BLOCK;
+
+fn block_layout() -> Layout {
+    Layout::from_size_align(BLOCK, 8).unwrap()
+}
+
+fn class_stats() -> SizeClassStats {
+    let index = BLOCK_SIZES.iter().position(|&size| size == BLOCK).unwrap();
+    HEAP.lock().stats().size_classes[index]
+}
+
+#[test_case]
+fn refill_splits_whole_page() {
+    let ptr = alloc(&HEAP, block_layout());
+    let class = class_stats();
+    assert_eq!(class.refills, 1);
+    assert_eq!(class.pages, 1);
+    assert_eq!(class.free_blocks, BLOCKS_PER_PAGE - 1);
+    // 下一个块来自同一页，不需要再补充
+    let next = alloc(&HEAP, block_layout());
+    assert_eq!(next as usize, ptr as usize + BLOCK);
+    assert_eq!(class_stats().refills, 1);
+    unsafe {
+        HEAP.dealloc(next, block_layout());
+        HEAP.dealloc(ptr, block_layout());
+    }
+    // 最后一页会被保留，显式回收后才还给后备分配器
+    assert_eq!(class_stats().pages, 1);
+    assert_eq!(HEAP.lock().reclaim(), 4096);
+    assert_eq!(class_stats().pages, 0);
+}
+
+#[test_case]
+fn spike_pages_are_returned_to_fallback() {
+    const PAGES: usize = 10;
+    let mut blocks = [core::ptr::null_mut(); PAGES * BLOCKS_PER_PAGE];
+    for block in blocks.iter_mut() {
+        *block = alloc(&HEAP, block_layout());
+    }
+    assert_eq!(class_stats().pages, PAGES);
+    for &block in blocks.iter() {
+        unsafe { HEAP.dealloc(block, block_layout()) };
+    }
+    // 高峰过去后最多保留一页空闲块
+    assert!(class_stats().pages <= 1);
+
+    // 回收的页可以用于大的分配，否则 40 KiB 的块页会让它失败
+    let large = Layout::from_size_align(48 * 1024, 8).unwrap();
+    let ptr = alloc(&HEAP, large);
+    unsafe { HEAP.dealloc(ptr, large) };
+    HEAP.lock().reclaim();
+}
+
+#[test_case]
+fn reclaim_empties_fallback_heap() {
+    let ptr = alloc(&HEAP, Layout::from_size_align(16, 16).unwrap());
+    unsafe { HEAP.dealloc(ptr, Layout::from_size_align(16, 16).unwrap()) };
+    HEAP.lock().reclaim();
+    let stats = HEAP.lock().stats();
+    assert_eq!(stats.fallback_bytes_in_use, 0);
+    assert!(stats.size_classes.iter().all(|class| class.pages == 0));
+}
+
+#[test_case]
+fn realloc_within_block_size_stays_in_place() {
+    let layout = Layout::from_size_align(40, 8).unwrap();
+    let ptr = alloc(&HEAP, layout);
+    let grown = unsafe { HEAP.realloc(ptr, layout, BLOCK) };
+    assert_eq!(grown, ptr);
+    assert_eq!(HEAP.lock().stats().bytes_in_use, BLOCK);
+    // 超出块大小后换到更大的块
+    let moved = unsafe { HEAP.realloc(grown, block_layout(), BLOCK + 1) };
+    assert!(!moved.is_null());
+    assert_ne!(moved, ptr);
+    unsafe { HEAP.dealloc(moved, Layout::from_size_align(BLOCK + 1, 8).unwrap()) };
+    HEAP.lock().reclaim();
+}
+
+#[test_case]
+fn realloc_in_fallback_heap_stays_in_place() {
+    let layout = Layout::from_size_align(4096, 8).unwrap();
+    let ptr = alloc(&HEAP, layout);
+    // 超过最大的块大小，由后备分配器原地扩展到后面的空闲区域
+    let grown = unsafe { HEAP.realloc(ptr, layout, 8192) };
+    assert_eq!(grown, ptr);
+    let stats = HEAP.lock().stats();
+    assert_eq!(stats.bytes_in_use, 8192);
+    assert_eq!(stats.fallback_bytes_in_use, 8192);
+    unsafe { HEAP.dealloc(grown, Layout::from_size_align(8192, 8).unwrap()) };
+    HEAP.lock().reclaim();
+}
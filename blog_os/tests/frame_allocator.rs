@@ -0,0 +1,96 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::memory::{self, bitmap::BitmapFrameAllocator};
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use spin::Mutex;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTableFlags,
+};
+
+entry_point!(main);
+
+// 测试函数没有参数，只能通过静态变量共享映射器和帧分配器
+static MAPPER: Mutex<Option<OffsetPageTable<'static>>> = Mutex::new(None);
+static FRAME_ALLOCATOR: Mutex<Option<BitmapFrameAllocator>> = Mutex::new(None);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    *MAPPER.lock() = Some(mapper);
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn freed_frame_is_reused() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+    let frame = allocator.allocate_frame().unwrap();
+    unsafe { allocator.deallocate_frame(frame) };
+    assert_eq!(allocator.allocate_frame(), Some(frame));
+    unsafe { allocator.deallocate_frame(frame) };
+}
+
+#[test_case]
+fn frames_are_unique() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+    let free_before = allocator.free_frames();
+    let mut frames = [None; 128];
+    for slot in frames.iter_mut() {
+        *slot = allocator.allocate_frame();
+    }
+    for (i, a) in frames.iter().enumerate() {
+        assert!(a.is_some());
+        assert!(frames[i + 1..].iter().all(|b| b != a));
+    }
+    assert_eq!(allocator.free_frames(), free_before - frames.len());
+    for frame in frames.iter().flatten() {
+        unsafe { allocator.deallocate_frame(*frame) };
+    }
+    assert_eq!(allocator.free_frames(), free_before);
+}
+
+#[test_case]
+fn map_unmap_returns_frames() {
+    let mut mapper_guard = MAPPER.lock();
+    let mapper = mapper_guard.as_mut().unwrap();
+    let mut allocator_guard = FRAME_ALLOCATOR.lock();
+    let allocator = allocator_guard.as_mut().unwrap();
+
+    let page: Page = Page::containing_address(VirtAddr::new(0xdead_beaf_0000));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    // 第一次映射可能会分配中间页表，之后空闲帧数量应该保持不变
+    let mut free_after_first = None;
+    for i in 0..1000u64 {
+        let frame = allocator.allocate_frame().unwrap();
+        unsafe {
+            mapper
+                .map_to(page, frame, flags, allocator)
+                .unwrap()
+                .flush()
+        };
+        unsafe { page.start_address().as_mut_ptr::<u64>().write_volatile(i) };
+        let (frame, flush) = mapper.unmap(page).unwrap();
+        flush.flush();
+        unsafe { allocator.deallocate_frame(frame) };
+        let free = allocator.free_frames();
+        assert_eq!(*free_after_first.get_or_insert(free), free);
+    }
+}
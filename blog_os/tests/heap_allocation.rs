@@ -13,14 +13,18 @@ entry_point!(main);
 
 fn main(boot_info: &'static BootInfo) -> ! {
     use blog_os::allocator;
-    use blog_os::memory::{self, BootInfoFrameAllocator};
+    use blog_os::memory::{self, bitmap::BitmapFrameAllocator};
     use x86_64::VirtAddr;
 
     blog_os::init();
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
-    let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
-    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap init failed");
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::install(mapper, frame_allocator);
+    allocator::init_heap().expect("heap init failed");
+    // 全局分配器由 heap-* feature 选择，打印出来方便对比不同分配器的结果
+    blog_os::serial_println!("heap allocator: {}", allocator::ALLOCATOR_NAME);
 
     test_main();
     loop {}
@@ -69,3 +73,38 @@ fn many_boxes_long_lived() {
     }
     assert_eq!(*long_lived, 1);
 }
+
+#[test_case]
+fn heap_grows_beyond_initial_size() {
+    // 一次性分配超过初始堆大小的内存，堆需要映射更多页面
+    let n = HEAP_SIZE;
+    let mut vec = Vec::with_capacity(n);
+    for i in 0..n {
+        vec.push(i as u64);
+    }
+    assert_eq!(vec.iter().sum::<u64>(), (n as u64 - 1) * n as u64 / 2);
+}
+
+#[test_case]
+fn heap_limit_is_respected() {
+    use blog_os::allocator::{heap_limit, set_heap_limit};
+    let old_limit = heap_limit();
+    set_heap_limit(2 * 1024 * 1024);
+    // 超过上限后分配失败，而不是继续增长
+    let mut vec: Vec<u8> = Vec::new();
+    assert!(vec.try_reserve_exact(4 * 1024 * 1024).is_err());
+    set_heap_limit(old_limit);
+    assert!(vec.try_reserve_exact(4 * 1024 * 1024).is_ok());
+}
+
+#[cfg(all(feature = "heap-bump", not(feature = "heap-debug")))]
+#[test_case]
+fn bump_grows_last_allocation_in_place() {
+    let mut vec: Vec<u64> = Vec::with_capacity(16);
+    vec.extend(0..16);
+    let ptr = vec.as_ptr();
+    vec.reserve_exact(1024);
+    // 最后一次分配直接移动 next，不需要复制
+    assert_eq!(vec.as_ptr(), ptr);
+    assert_eq!(vec.iter().sum::<u64>(), 120);
+}
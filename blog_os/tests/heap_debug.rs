@@ -0,0 +1,113 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+mod common;
+
+use blog_os::allocator::Locked;
+use blog_os::allocator::debug::{self, DebugAllocator};
+use blog_os::allocator::linked_list::LinkListAllocator;
+use bootloader::{BootInfo, entry_point};
+use common::{ARENA_SIZE, Arena, alloc};
+use core::alloc::{GlobalAlloc, Layout};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+static ARENA: Arena = Arena::new();
+static INNER: Locked<LinkListAllocator> = Locked::new(LinkListAllocator::new());
+static HEAP: DebugAllocator<Locked<LinkListAllocator>> = DebugAllocator::new(&INNER);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    unsafe { INNER.lock().init(ARENA.start(), ARENA_SIZE) };
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+/// 内层分配器的所有内存都被还回来了
+fn assert_inner_fully_free() {
+    assert_eq!(INNER.lock().free_regions(), 1);
+}
+
+#[test_case]
+fn fresh_memory_is_poisoned() {
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let errors = debug::errors();
+    let ptr = alloc(&HEAP, layout);
+    for offset in 0..layout.size() {
+        assert_eq!(unsafe { ptr.add(offset).read() }, 0xcd);
+    }
+    unsafe { HEAP.dealloc(ptr, layout) };
+    assert_eq!(debug::errors(), errors);
+    assert_inner_fully_free();
+}
+
+#[test_case]
+fn freed_memory_is_poisoned() {
+    let layout = Layout::from_size_align(64, 64).unwrap();
+    let ptr = alloc(&HEAP, layout);
+    unsafe { ptr.write_bytes(0, layout.size()) };
+    unsafe { HEAP.dealloc(ptr, layout) };
+    // 内层分配器只在块开头写入链表节点，用户内存中间仍然是填充的字节
+    assert_eq!(unsafe { ptr.add(32).read_volatile() }, 0xdd);
+    assert_inner_fully_free();
+}
+
+#[test_case]
+fn double_free_is_detected() {
+    let layout = Layout::from_size_align(48, 8).unwrap();
+    let errors = debug::errors();
+    let ptr = alloc(&HEAP, layout);
+    unsafe {
+        HEAP.dealloc(ptr, layout);
+        HEAP.dealloc(ptr, layout);
+    }
+    assert_eq!(debug::errors(), errors + 1);
+    // 第二次释放被忽略，内层分配器的空闲链表没有被破坏
+    assert_inner_fully_free();
+}
+
+#[test_case]
+fn layout_mismatch_is_detected() {
+    let errors = debug::errors();
+    let ptr = alloc(&HEAP, Layout::from_size_align(24, 8).unwrap());
+    unsafe { HEAP.dealloc(ptr, Layout::from_size_align(32, 8).unwrap()) };
+    assert_eq!(debug::errors(), errors + 1);
+    // 按分配时记录的布局释放，内存没有丢失
+    assert_inner_fully_free();
+}
+
+#[test_case]
+fn overflow_is_detected() {
+    let layout = Layout::from_size_align(10, 2).unwrap();
+    let errors = debug::errors();
+    let ptr = alloc(&HEAP, layout);
+    unsafe {
+        ptr.add(layout.size()).write(0);
+        HEAP.dealloc(ptr, layout);
+    }
+    assert_eq!(debug::errors(), errors + 1);
+    assert_inner_fully_free();
+}
+
+#[test_case]
+fn underflow_is_detected() {
+    let layout = Layout::from_size_align(10, 2).unwrap();
+    let errors = debug::errors();
+    let ptr = alloc(&HEAP, layout);
+    unsafe {
+        ptr.sub(1).write(0);
+        HEAP.dealloc(ptr, layout);
+    }
+    assert_eq!(debug::errors(), errors + 1);
+    assert_inner_fully_free();
+}
@@ -0,0 +1,78 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use blog_os::memory::{self, bitmap::BitmapFrameAllocator, wx};
+use blog_os::{QemuExitCode, allocator, exit_qemu, serial_print, serial_println};
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use lazy_static::lazy_static;
+use x86_64::VirtAddr;
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+
+entry_point!(main);
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+// 跳转到堆上应该触发页错误，所以需要一个专门处理页错误的 IDT
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+        idt
+    };
+}
+
+// 要跳转过去执行的堆地址
+static TARGET: AtomicU64 = AtomicU64::new(0);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("heap_execute::jump_into_heap_faults...\t");
+    blog_os::gdt::init();
+    TEST_IDT.load();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::install(mapper, frame_allocator);
+    allocator::init_heap().expect("heap initialization failed");
+
+    let violations = memory::with_kernel_memory(|mapper, _| wx::violations(mapper)).unwrap();
+    assert_eq!(violations, 0, "writable and executable mappings left");
+
+    // 在堆上放一条 `ret` 指令，如果堆可以执行，调用会直接返回
+    let code = Box::leak(Box::new([0xc3u8; 16]));
+    TARGET.store(code.as_ptr() as u64, Ordering::SeqCst);
+    let entry: extern "C" fn() = unsafe { core::mem::transmute(code.as_ptr()) };
+    entry();
+
+    serial_println!("[test did not fault]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+// 页错误处理函数，检查错误是由取指令引起的，并且发生在堆地址上
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    assert!(
+        error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH),
+        "page fault not caused by instruction fetch: {:?}",
+        error_code
+    );
+    assert_eq!(Cr2::read().as_u64(), TARGET.load(Ordering::SeqCst));
+
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
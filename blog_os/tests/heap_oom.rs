@@ -0,0 +1,89 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+mod common;
+
+use blog_os::allocator::Locked;
+use blog_os::allocator::linked_list::LinkListAllocator;
+use blog_os::allocator::oom::{self, ReclaimingAllocator};
+use bootloader::{BootInfo, entry_point};
+use common::{ARENA_SIZE, Arena, alloc};
+use core::alloc::{GlobalAlloc, Layout};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+entry_point!(main);
+
+static ARENA: Arena = Arena::new();
+static INNER: Locked<LinkListAllocator> = Locked::new(LinkListAllocator::new());
+static HEAP: ReclaimingAllocator<Locked<LinkListAllocator>> = ReclaimingAllocator::new(&INNER);
+
+/// 模拟一个缓存：占用堆的一大块，回收时把它释放
+static CACHE: AtomicUsize = AtomicUsize::new(0);
+/// 回收函数被调用的次数
+static RECLAIM_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+fn cache_layout() -> Layout {
+    Layout::from_size_align(48 * 1024, 8).unwrap()
+}
+
+fn shrink_cache() -> usize {
+    RECLAIM_CALLS.fetch_add(1, Ordering::Relaxed);
+    match CACHE.swap(0, Ordering::Relaxed) {
+        0 => 0,
+        ptr => {
+            unsafe { HEAP.dealloc(ptr as *mut u8, cache_layout()) };
+            cache_layout().size()
+        }
+    }
+}
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    unsafe { INNER.lock().init(ARENA.start(), ARENA_SIZE) };
+    oom::register_reclaimer("test cache", shrink_cache).unwrap();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn allocation_retries_after_reclaim() {
+    let cache = alloc(&HEAP, cache_layout());
+    CACHE.store(cache as usize, Ordering::Relaxed);
+
+    // 缓存还在时放不下，回收缓存之后重试成功
+    let calls = RECLAIM_CALLS.load(Ordering::Relaxed);
+    let layout = Layout::from_size_align(32 * 1024, 8).unwrap();
+    let ptr = alloc(&HEAP, layout);
+    assert_eq!(RECLAIM_CALLS.load(Ordering::Relaxed), calls + 1);
+    assert_eq!(CACHE.load(Ordering::Relaxed), 0);
+    unsafe { HEAP.dealloc(ptr, layout) };
+    assert_eq!(INNER.lock().free_regions(), 1);
+}
+
+#[test_case]
+fn allocation_fails_when_nothing_is_reclaimed() {
+    let calls = RECLAIM_CALLS.load(Ordering::Relaxed);
+    let layout = Layout::from_size_align(ARENA_SIZE * 2, 8).unwrap();
+    assert!(unsafe { HEAP.alloc(layout) }.is_null());
+    // 回收函数没有释放任何内存，不会再重试
+    assert_eq!(RECLAIM_CALLS.load(Ordering::Relaxed), calls + 1);
+}
+
+#[test_case]
+fn successful_allocation_does_not_reclaim() {
+    let calls = RECLAIM_CALLS.load(Ordering::Relaxed);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let ptr = alloc(&HEAP, layout);
+    unsafe { HEAP.dealloc(ptr, layout) };
+    assert_eq!(RECLAIM_CALLS.load(Ordering::Relaxed), calls);
+}
@@ -0,0 +1,118 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use blog_os::allocator::{self, stats::HeapStats};
+use blog_os::memory::{self, bitmap::BitmapFrameAllocator};
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::install(mapper, frame_allocator);
+    allocator::init_heap().expect("heap init failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+fn stats() -> HeapStats {
+    allocator::heap_stats().expect("allocator is locked")
+}
+
+#[test_case]
+fn reports_selected_allocator() {
+    let stats = stats();
+    assert_eq!(stats.allocator, allocator::ALLOCATOR_NAME);
+    assert!(stats.heap_size >= allocator::HEAP_SIZE);
+    assert!(stats.largest_free_region > 0);
+    assert!(stats.largest_free_region <= stats.heap_size);
+}
+
+#[test_case]
+fn counts_allocations_and_frees() {
+    let before = stats();
+    let value = Box::new([0u64; 4]);
+    let allocated = stats();
+    assert_eq!(allocated.allocations, before.allocations + 1);
+    // 堆调试模式下统计的是加上头部和红区之后的大小
+    #[cfg(not(feature = "heap-debug"))]
+    assert_eq!(allocated.bytes_in_use, before.bytes_in_use + 32);
+    #[cfg(feature = "heap-debug")]
+    assert!(allocated.bytes_in_use > before.bytes_in_use + 32);
+    drop(value);
+    let freed = stats();
+    assert_eq!(freed.frees, before.frees + 1);
+    assert_eq!(freed.bytes_in_use, before.bytes_in_use);
+}
+
+#[test_case]
+fn tracks_peak_usage() {
+    let before = stats();
+    let vec: Vec<u8> = Vec::with_capacity(4096);
+    drop(vec);
+    let after = stats();
+    assert_eq!(after.bytes_in_use, before.bytes_in_use);
+    assert!(after.peak_bytes_in_use >= before.bytes_in_use + 4096);
+}
+
+#[test_case]
+fn largest_free_region_fits_without_growing() {
+    let before = stats();
+    // 不超过最大空闲区域的一半，任何分配器都不需要扩容
+    let vec: Vec<u8> = Vec::with_capacity(before.largest_free_region / 2);
+    assert_eq!(stats().heap_size, before.heap_size);
+    drop(vec);
+}
+
+// 堆调试模式会改变请求的大小，块大小的统计对不上
+#[cfg(all(feature = "heap-fixed-size-block", not(feature = "heap-debug")))]
+#[test_case]
+fn counts_size_class_hits_and_fallback() {
+    use blog_os::allocator::fixed_size_block::BLOCK_SIZES;
+    let class = BLOCK_SIZES.iter().position(|&size| size == 64).unwrap();
+
+    // 第一次可能需要从后备分配器切出新块，释放后再分配一定命中链表
+    drop(Box::new([0u8; 64]));
+    let before = stats();
+    assert!(before.size_classes[class].free_blocks > 0);
+    let value = Box::new([0u8; 64]);
+    let after = stats();
+    assert_eq!(
+        after.size_classes[class].hits,
+        before.size_classes[class].hits + 1
+    );
+    assert_eq!(
+        after.size_classes[class].free_blocks,
+        before.size_classes[class].free_blocks - 1
+    );
+    drop(value);
+
+    // 超过最大块大小的分配直接由后备分配器满足
+    let large: Vec<u8> = Vec::with_capacity(4096);
+    let fallback = stats();
+    assert_eq!(
+        fallback.fallback_allocations,
+        after.fallback_allocations + 1
+    );
+    assert!(fallback.fallback_bytes_in_use >= after.fallback_bytes_in_use + 4096);
+    drop(large);
+}
@@ -0,0 +1,124 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+mod common;
+
+use blog_os::allocator::Locked;
+use blog_os::allocator::linked_list::LinkListAllocator;
+use blog_os::allocator::trace::{self, TraceKind, TraceRecord, TracingAllocator};
+use bootloader::{BootInfo, entry_point};
+use common::{ARENA_SIZE, Arena, alloc};
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+static ARENA: Arena = Arena::new();
+static INNER: Locked<LinkListAllocator> = Locked::new(LinkListAllocator::new());
+static HEAP: TracingAllocator<Locked<LinkListAllocator>> = TracingAllocator::new(&INNER);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    unsafe { INNER.lock().init(ARENA.start(), ARENA_SIZE) };
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+/// 不需要堆的格式化缓冲区
+struct Buffer {
+    bytes: [u8; 128],
+    len: usize,
+}
+
+impl Buffer {
+    fn format(record: &TraceRecord) -> Buffer {
+        let mut buffer = Buffer {
+            bytes: [0; 128],
+            len: 0,
+        };
+        write!(buffer, "{}", record).unwrap();
+        buffer
+    }
+
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap()
+    }
+}
+
+impl Write for Buffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        if end > self.bytes.len() {
+            return Err(fmt::Error);
+        }
+        self.bytes[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+#[test_case]
+fn alloc_and_free_are_recorded() {
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let records = trace::records();
+    let ptr = alloc(&HEAP, layout);
+    assert_eq!(trace::records(), records + 1);
+    unsafe { HEAP.dealloc(ptr, layout) };
+    assert_eq!(trace::records(), records + 2);
+}
+
+#[test_case]
+fn realloc_is_recorded_as_free_and_alloc() {
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let ptr = alloc(&HEAP, layout);
+    let records = trace::records();
+    let ptr = unsafe { HEAP.realloc(ptr, layout, 128) };
+    assert!(!ptr.is_null());
+    assert_eq!(trace::records(), records + 2);
+    unsafe { HEAP.dealloc(ptr, Layout::from_size_align(128, 8).unwrap()) };
+}
+
+#[test_case]
+fn disabled_tracing_records_nothing() {
+    let layout = Layout::from_size_align(16, 16).unwrap();
+    trace::disable();
+    let records = trace::records();
+    let ptr = alloc(&HEAP, layout);
+    unsafe { HEAP.dealloc(ptr, layout) };
+    assert_eq!(trace::records(), records);
+    trace::enable();
+}
+
+#[test_case]
+fn record_format() {
+    let mut record = TraceRecord {
+        kind: TraceKind::Alloc,
+        seq: 0x2a,
+        ptr: 0x4444_4444_0010,
+        layout: Layout::from_size_align(0x30, 8).unwrap(),
+        task: Some(3),
+        callers: [0x20_1234, 0x20_5678, 0, 0],
+        depth: 2,
+    };
+    assert_eq!(
+        Buffer::format(&record).as_str(),
+        "@heap a 2a 444444440010 30 8 3 201234,205678"
+    );
+    record.kind = TraceKind::Free;
+    record.task = None;
+    record.depth = 0;
+    assert_eq!(
+        Buffer::format(&record).as_str(),
+        "@heap f 2a 444444440010 30 8 - -"
+    );
+}
@@ -0,0 +1,139 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+mod common;
+
+use blog_os::allocator::Locked;
+use blog_os::allocator::linked_list::LinkListAllocator;
+use blog_os::allocator::stats::HeapStatistics;
+use bootloader::{BootInfo, entry_point};
+use common::{Arena, alloc};
+use core::alloc::{GlobalAlloc, Layout};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+const ARENA_SIZE: usize = 128 * 1024;
+
+static ARENA: Arena<ARENA_SIZE> = Arena::new();
+static HEAP: Locked<LinkListAllocator> = Locked::new(LinkListAllocator::new());
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    unsafe { HEAP.lock().init(ARENA.start(), ARENA_SIZE) };
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+/// 每个测试结束时都要释放所有分配，让下一个测试从一整块空闲区域开始
+fn assert_fully_free() {
+    let heap = HEAP.lock();
+    assert_eq!(heap.free_regions(), 1);
+    assert_eq!(heap.stats().largest_free_region, ARENA_SIZE);
+}
+
+#[test_case]
+fn adjacent_regions_merge() {
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let a = alloc(&HEAP, layout);
+    let b = alloc(&HEAP, layout);
+    let c = alloc(&HEAP, layout);
+    unsafe {
+        HEAP.dealloc(a, layout);
+        assert_eq!(HEAP.lock().free_regions(), 2);
+        // c 和后面剩余的空间合并
+        HEAP.dealloc(c, layout);
+        assert_eq!(HEAP.lock().free_regions(), 2);
+        // b 把前后两个区域连成一整块
+        HEAP.dealloc(b, layout);
+    }
+    assert_fully_free();
+}
+
+#[test_case]
+fn churn_returns_to_one_region() {
+    const SLOTS: usize = 64;
+    let mut slots: [Option<(*mut u8, Layout)>; SLOTS] = [None; SLOTS];
+    // xorshift 伪随机数，保证每次运行的分配序列相同
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state as usize
+    };
+    for _ in 0..10_000 {
+        let slot = &mut slots[next() % SLOTS];
+        match slot.take() {
+            Some((ptr, layout)) => unsafe { HEAP.dealloc(ptr, layout) },
+            None => {
+                let size = next() % 1024 + 1;
+                let align = 1 << (next() % 7); // 1 到 64 字节对齐
+                let layout = Layout::from_size_align(size, align).unwrap();
+                let ptr = alloc(&HEAP, layout);
+                assert_eq!(ptr as usize % align, 0);
+                *slot = Some((ptr, layout));
+            }
+        }
+    }
+    for (ptr, layout) in slots.iter_mut().filter_map(Option::take) {
+        unsafe { HEAP.dealloc(ptr, layout) };
+    }
+    assert_fully_free();
+}
+
+#[test_case]
+fn whole_heap_allocatable_after_churn() {
+    // 前面的测试已经反复分配和释放过，合并后整个堆仍然能一次分配出去
+    let layout = Layout::from_size_align(ARENA_SIZE, 8).unwrap();
+    let ptr = alloc(&HEAP, layout);
+    assert_eq!(HEAP.lock().free_regions(), 0);
+    unsafe { HEAP.dealloc(ptr, layout) };
+    assert_fully_free();
+}
+
+#[test_case]
+fn realloc_grows_and_shrinks_in_place() {
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let ptr = alloc(&HEAP, layout);
+    unsafe { ptr.write_bytes(0xab, layout.size()) };
+    // 后面是剩余的整块空闲区域 -> 原地增长
+    let grown = unsafe { HEAP.realloc(ptr, layout, 256) };
+    assert_eq!(grown, ptr);
+    assert_eq!(unsafe { grown.add(63).read() }, 0xab);
+    // 原地缩小，多出的部分还回链表并和后面的区域合并
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    let shrunk = unsafe { HEAP.realloc(grown, layout, 128) };
+    assert_eq!(shrunk, ptr);
+    assert_eq!(HEAP.lock().free_regions(), 1);
+    unsafe { HEAP.dealloc(shrunk, Layout::from_size_align(128, 8).unwrap()) };
+    assert_fully_free();
+}
+
+#[test_case]
+fn realloc_moves_when_next_block_is_used() {
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let a = alloc(&HEAP, layout);
+    let b = alloc(&HEAP, layout);
+    unsafe { a.write_bytes(0x5a, layout.size()) };
+    let moved = unsafe { HEAP.realloc(a, layout, 512) };
+    assert!(!moved.is_null());
+    assert_ne!(moved, a);
+    for offset in 0..layout.size() {
+        assert_eq!(unsafe { moved.add(offset).read() }, 0x5a);
+    }
+    unsafe {
+        HEAP.dealloc(b, layout);
+        HEAP.dealloc(moved, Layout::from_size_align(512, 8).unwrap());
+    }
+    assert_fully_free();
+}
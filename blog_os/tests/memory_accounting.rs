@@ -0,0 +1,121 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::memory::{
+    self,
+    accounting::{self, FrameOwner, MemoryMapSummary},
+    bitmap::BitmapFrameAllocator,
+    stack,
+    vma::{self, AreaKind, KERNEL_SPACE, VmError},
+    wx,
+};
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use bootloader::{BootInfo, entry_point};
+use conquer_once::spin::OnceCell;
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::Page;
+use x86_64::structures::paging::mapper::MapToError;
+
+entry_point!(main);
+
+static MEMORY_MAP: OnceCell<&'static MemoryMap> = OnceCell::uninit();
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    MEMORY_MAP.init_once(|| &boot_info.memory_map);
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::install(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn memory_map_summary_matches_frame_allocator() {
+    let summary = MemoryMapSummary::new(MEMORY_MAP.get().unwrap());
+    let stats = accounting::stats().unwrap();
+    // 帧分配器管理的正好是所有可用区域
+    assert_eq!(
+        summary.bytes(MemoryRegionType::Usable),
+        stats.total_frames as u64 * 4096
+    );
+    assert!(summary.bytes(MemoryRegionType::Kernel) > 0);
+    accounting::report_memory_map(MEMORY_MAP.get().unwrap());
+}
+
+#[test_case]
+fn used_and_free_add_up() {
+    let stats = accounting::stats().unwrap();
+    assert_eq!(stats.used_frames + stats.free_frames, stats.total_frames);
+    assert!(stats.used_frames > 0); // 至少位图自己占用了帧
+}
+
+#[test_case]
+fn stack_frames_are_charged_to_stacks() {
+    let before = accounting::stats().unwrap();
+    let stack = stack::alloc_stack(4).unwrap();
+    let after = accounting::stats().unwrap();
+    assert_eq!(after.stack_frames, before.stack_frames + 4);
+    // 新建的页表也被记录下来，已用帧数正好是所有使用者增加的总和
+    let page_tables = after.page_table_frames - before.page_table_frames;
+    assert_eq!(after.used_frames, before.used_frames + 4 + page_tables);
+
+    unsafe { stack::free_stack(stack).unwrap() };
+    assert_eq!(
+        accounting::owned_frames(FrameOwner::Stack),
+        before.stack_frames
+    );
+    accounting::report();
+}
+
+#[test_case]
+fn failed_mapping_is_not_charged() {
+    let area = KERNEL_SPACE
+        .lock()
+        .reserve(4096, AreaKind::Other, wx::DATA_FLAGS)
+        .unwrap();
+    let pages = Page::range(
+        Page::containing_address(area.start),
+        Page::containing_address(area.end()),
+    );
+    let map = || {
+        memory::with_kernel_memory(|mapper, frame_allocator| {
+            vma::map_pages(
+                pages,
+                wx::DATA_FLAGS,
+                FrameOwner::Other,
+                mapper,
+                frame_allocator,
+            )
+        })
+        .unwrap()
+    };
+    map().unwrap();
+    let before = accounting::stats().unwrap();
+    // 页面已经映射过，第二次映射失败，分配的帧要还回去，也不能记账
+    assert!(matches!(
+        map(),
+        Err(VmError::Map(MapToError::PageAlreadyMapped(_)))
+    ));
+    let after = accounting::stats().unwrap();
+    assert_eq!(after.free_frames, before.free_frames);
+    assert_eq!(after.other_frames, before.other_frames);
+
+    memory::with_kernel_memory(|mapper, frame_allocator| {
+        let kernel_space = KERNEL_SPACE.lock();
+        unsafe { kernel_space.unmap(area.start, mapper, frame_allocator) }.unwrap();
+    });
+    KERNEL_SPACE.lock().release(area.start).unwrap();
+}
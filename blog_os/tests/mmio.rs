@@ -0,0 +1,101 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::memory::{
+    self,
+    bitmap::BitmapFrameAllocator,
+    mmio::{self, CacheMode},
+    vma::KERNEL_SPACE,
+};
+use bootloader::{BootInfo, entry_point};
+use conquer_once::spin::OnceCell;
+use core::panic::PanicInfo;
+use volatile::Volatile;
+use x86_64::structures::paging::{PageTableFlags, Translate};
+use x86_64::{PhysAddr, VirtAddr};
+
+entry_point!(main);
+
+static PHYS_MEM_OFFSET: OnceCell<VirtAddr> = OnceCell::uninit();
+
+// VGA 文本缓冲区的物理地址，可以当作一块总是存在的设备内存
+const VGA_BUFFER: u64 = 0xb8000;
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    PHYS_MEM_OFFSET.init_once(|| VirtAddr::new(boot_info.physical_memory_offset));
+    let phys_mem_offset = *PHYS_MEM_OFFSET.get().unwrap();
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::install(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+/// 测试用的寄存器块，覆盖 VGA 缓冲区的前两行
+#[repr(C)]
+struct TwoRows {
+    cells: [Volatile<u16>; 160],
+}
+
+fn translate(addr: VirtAddr) -> Option<(PhysAddr, PageTableFlags)> {
+    memory::with_kernel_memory(|mapper, _| match mapper.translate(addr) {
+        x86_64::structures::paging::mapper::TranslateResult::Mapped {
+            frame,
+            offset,
+            flags,
+        } => Some((frame.start_address() + offset, flags)),
+        _ => None,
+    })
+    .unwrap()
+}
+
+#[test_case]
+fn ioremap_maps_device_memory() {
+    let mut rows =
+        mmio::ioremap::<TwoRows>(PhysAddr::new(VGA_BUFFER), CacheMode::Uncached).unwrap();
+    let (phys, flags) = translate(rows.virt_addr()).unwrap();
+    assert_eq!(phys, PhysAddr::new(VGA_BUFFER));
+    assert!(flags.contains(PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH));
+    assert!(flags.contains(PageTableFlags::NO_EXECUTE));
+
+    // 通过映射写入的值可以从物理内存映射中读到
+    rows.cells[159].write(0x0f41);
+    let alias = (*PHYS_MEM_OFFSET.get().unwrap() + (VGA_BUFFER + 2 * 159)).as_ptr::<u16>();
+    assert_eq!(unsafe { alias.read_volatile() }, 0x0f41);
+    assert_eq!(rows.cells[159].read(), 0x0f41);
+}
+
+#[test_case]
+fn ioremap_keeps_page_offset() {
+    let phys = PhysAddr::new(VGA_BUFFER + 0xff8);
+    // 跨越两页的 16 字节寄存器块
+    let regs = mmio::ioremap::<[Volatile<u64>; 2]>(phys, CacheMode::WriteThrough).unwrap();
+    assert_eq!(regs.virt_addr().as_u64() & 0xfff, 0xff8);
+    assert_eq!(translate(regs.virt_addr()).unwrap().0, phys);
+    assert_eq!(translate(regs.virt_addr() + 8u64).unwrap().0, phys + 8u64);
+    let (_, flags) = translate(regs.virt_addr()).unwrap();
+    assert!(flags.contains(PageTableFlags::WRITE_THROUGH));
+    assert!(!flags.contains(PageTableFlags::NO_CACHE));
+}
+
+#[test_case]
+fn drop_unmaps_and_releases_area() {
+    let regs =
+        mmio::ioremap::<Volatile<u32>>(PhysAddr::new(VGA_BUFFER), CacheMode::Uncached).unwrap();
+    let virt = regs.virt_addr();
+    assert!(KERNEL_SPACE.lock().find(virt).is_some());
+    drop(regs);
+    assert!(translate(virt).is_none());
+    assert!(KERNEL_SPACE.lock().find(virt).is_none());
+}
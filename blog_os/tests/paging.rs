@@ -0,0 +1,122 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::memory::{self, MappingSize, bitmap::BitmapFrameAllocator, dump, wx};
+use bootloader::{BootInfo, entry_point};
+use conquer_once::spin::OnceCell;
+use core::panic::PanicInfo;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::{PhysAddr, VirtAddr};
+
+entry_point!(main);
+
+static PHYS_MEM_OFFSET: OnceCell<VirtAddr> = OnceCell::uninit();
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    PHYS_MEM_OFFSET.init_once(|| VirtAddr::new(boot_info.physical_memory_offset));
+    let phys_mem_offset = *PHYS_MEM_OFFSET.get().unwrap();
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::install(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn translate_physical_memory_mapping() {
+    let offset = *PHYS_MEM_OFFSET.get().unwrap();
+    // 物理内存映射可能使用大页，翻译不能崩溃，并且要得到原来的物理地址
+    for phys in [0x1000u64, 0xb8000, 0x20_1234, 0x40_0000 - 8] {
+        let translation = unsafe { memory::translate(offset + phys, offset) }.unwrap();
+        assert_eq!(translation.phys_addr, PhysAddr::new(phys));
+        assert!(translation.flags.contains(PageTableFlags::PRESENT));
+    }
+}
+
+#[test_case]
+fn translate_unmapped_address() {
+    let offset = *PHYS_MEM_OFFSET.get().unwrap();
+    let addr = VirtAddr::new(0xdead_beaf_0000);
+    assert_eq!(unsafe { memory::translate(addr, offset) }, None);
+    assert_eq!(unsafe { memory::translate_addr(addr, offset) }, None);
+}
+
+#[test_case]
+fn dump_ranges_are_sorted_and_merged() {
+    let offset = *PHYS_MEM_OFFSET.get().unwrap();
+    let mut previous: Option<dump::MappedRange> = None;
+    let mut covers_offset = false;
+    memory::with_kernel_memory(|mapper, _| {
+        dump::for_each_range(mapper, |range| {
+            if let Some(previous) = previous {
+                // 区间按地址排序且互不重叠，相邻的区间属性一定不同
+                assert!(previous.end() <= range.start);
+                let mergeable = previous.end() == range.start
+                    && previous.phys_start + previous.size == range.phys_start
+                    && previous.page_size == range.page_size
+                    && previous.flags == range.flags;
+                assert!(!mergeable);
+            }
+            assert!(range.flags.contains(PageTableFlags::PRESENT));
+            assert_eq!(range.size % range.page_size.bytes(), 0);
+            if range.start <= offset && offset < range.end() {
+                // 物理内存映射的起点映射到物理地址 0
+                assert_eq!(range.phys_start + (offset - range.start), PhysAddr::new(0));
+                covers_offset = true;
+            }
+            previous = Some(range);
+        });
+        dump::dump(mapper);
+    })
+    .unwrap();
+    assert!(covers_offset);
+}
+
+#[test_case]
+fn dump_matches_translate() {
+    let offset = *PHYS_MEM_OFFSET.get().unwrap();
+    memory::with_kernel_memory(|mapper, _| {
+        dump::for_each_range(mapper, |range| {
+            let translation = unsafe { memory::translate(range.start, offset) }.unwrap();
+            assert_eq!(translation.phys_addr, range.phys_start);
+            assert_eq!(translation.size, range.page_size);
+            if range.page_size != MappingSize::Size4KiB {
+                assert!(translation.flags.contains(PageTableFlags::HUGE_PAGE));
+            }
+        });
+    })
+    .unwrap();
+}
+
+#[test_case]
+fn no_writable_executable_mappings() {
+    let violations = memory::with_kernel_memory(|mapper, _| wx::violations(mapper)).unwrap();
+    assert_eq!(violations, 0);
+}
+
+/// 放在 .rodata 中的数据
+static RODATA: [u8; 16] = [0x5a; 16];
+
+#[test_case]
+fn only_code_is_executable() {
+    let offset = *PHYS_MEM_OFFSET.get().unwrap();
+    let rodata = VirtAddr::from_ptr(core::hint::black_box(&RODATA));
+    let translation = unsafe { memory::translate(rodata, offset) }.unwrap();
+    assert!(translation.flags.contains(PageTableFlags::NO_EXECUTE));
+    assert!(!translation.flags.contains(PageTableFlags::WRITABLE));
+
+    let code = VirtAddr::new(blog_os::hlt_loop as *const () as u64);
+    let translation = unsafe { memory::translate(code, offset) }.unwrap();
+    assert!(!translation.flags.contains(PageTableFlags::NO_EXECUTE));
+}
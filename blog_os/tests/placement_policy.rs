@@ -0,0 +1,180 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+mod common;
+
+use blog_os::allocator::Locked;
+use blog_os::allocator::linked_list::{LinkListAllocator, PlacementPolicy};
+use blog_os::allocator::stats::HeapStatistics;
+use blog_os::serial_println;
+use bootloader::{BootInfo, entry_point};
+use common::Arena;
+use core::alloc::{GlobalAlloc, Layout};
+use core::arch::x86_64::_rdtsc;
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+/// 基准测试的序列最多同时占用 64 个 8 KiB 的分配，堆留出一倍的余量，
+/// 这样任何策略都不会分配失败，比较的是同样的一组操作
+const ARENA_SIZE: usize = 1024 * 1024;
+
+/// 每种策略一个独立的堆
+static ARENAS: [Arena<ARENA_SIZE>; 3] = [const { Arena::new() }; 3];
+static HEAPS: [Locked<LinkListAllocator>; 3] = [
+    Locked::new(LinkListAllocator::with_policy(PlacementPolicy::FirstFit)),
+    Locked::new(LinkListAllocator::with_policy(PlacementPolicy::BestFit)),
+    Locked::new(LinkListAllocator::with_policy(PlacementPolicy::NextFit)),
+];
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    for (heap, arena) in HEAPS.iter().zip(&ARENAS) {
+        unsafe { heap.lock().init(arena.start(), ARENA_SIZE) };
+    }
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+fn heap(policy: PlacementPolicy) -> &'static Locked<LinkListAllocator> {
+    HEAPS
+        .iter()
+        .find(|heap| heap.lock().policy() == policy)
+        .unwrap()
+}
+
+fn alloc(heap: &Locked<LinkListAllocator>, size: usize) -> usize {
+    common::alloc(heap, Layout::from_size_align(size, 8).unwrap()) as usize
+}
+
+fn dealloc(heap: &Locked<LinkListAllocator>, addr: usize, size: usize) {
+    unsafe { heap.dealloc(addr as *mut u8, Layout::from_size_align(size, 8).unwrap()) };
+}
+
+/// 在空的堆中留下一个 256 字节和一个 128 字节的空隙，然后分配 100 字节
+///
+/// 返回 0 表示用了第一个空隙，1 表示第二个，2 表示后面剩下的大区域
+fn place_between_holes(policy: PlacementPolicy) -> usize {
+    let heap = heap(policy);
+    let a = alloc(heap, 256);
+    let s1 = alloc(heap, 64);
+    let b = alloc(heap, 128);
+    let s2 = alloc(heap, 64);
+    dealloc(heap, a, 256);
+    dealloc(heap, b, 128);
+
+    let ptr = alloc(heap, 100);
+    let choice = if ptr == a {
+        0
+    } else if ptr == b {
+        1
+    } else {
+        assert!(ptr > s2);
+        2
+    };
+    for (addr, size) in [(ptr, 100), (s1, 64), (s2, 64)] {
+        dealloc(heap, addr, size);
+    }
+    assert_eq!(heap.lock().free_regions(), 1);
+    choice
+}
+
+#[test_case]
+fn first_fit_uses_first_hole() {
+    assert_eq!(place_between_holes(PlacementPolicy::FirstFit), 0);
+}
+
+#[test_case]
+fn best_fit_uses_smallest_hole() {
+    assert_eq!(place_between_holes(PlacementPolicy::BestFit), 1);
+}
+
+#[test_case]
+fn next_fit_continues_after_last_allocation() {
+    assert_eq!(place_between_holes(PlacementPolicy::NextFit), 2);
+}
+
+/// 同一个分配序列在每种策略下的结果
+struct TraceResult {
+    cycles: u64,                // 执行整个序列用的 TSC 周期数
+    peak_fragmentation: usize,  // 采样到的最大碎片率
+    final_fragmentation: usize, // 序列结束、释放所有分配前的碎片率
+}
+
+/// 用固定种子生成分配和释放序列，在 `heap` 上执行
+fn run_trace(heap: &Locked<LinkListAllocator>) -> TraceResult {
+    const SLOTS: usize = 64;
+    const STEPS: usize = 20_000;
+    let mut slots: [Option<(*mut u8, Layout)>; SLOTS] = [None; SLOTS];
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut next = || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state as usize
+    };
+    let mut result = TraceResult {
+        cycles: 0,
+        peak_fragmentation: 0,
+        final_fragmentation: 0,
+    };
+
+    let start = unsafe { _rdtsc() };
+    for step in 0..STEPS {
+        let slot = &mut slots[next() % SLOTS];
+        match slot.take() {
+            Some((ptr, layout)) => unsafe { heap.dealloc(ptr, layout) },
+            None => {
+                // 大部分是小对象，偶尔有几 KiB 的缓冲区
+                let size = if next() % 16 == 0 {
+                    next() % 8192 + 1
+                } else {
+                    next() % 256 + 1
+                };
+                let layout = Layout::from_size_align(size, 8).unwrap();
+                // 分配失败时测试失败，否则各策略执行的操作不同，周期数没有可比性
+                *slot = Some((common::alloc(heap, layout), layout));
+            }
+        }
+        if step % 256 == 0 {
+            let fragmentation = heap.lock().stats().fragmentation();
+            result.peak_fragmentation = result.peak_fragmentation.max(fragmentation);
+        }
+    }
+    result.cycles = unsafe { _rdtsc() } - start;
+    result.final_fragmentation = heap.lock().stats().fragmentation();
+
+    for (ptr, layout) in slots.iter_mut().filter_map(Option::take) {
+        unsafe { heap.dealloc(ptr, layout) };
+    }
+    result
+}
+
+#[test_case]
+fn benchmark_policies() {
+    for heap in HEAPS.iter() {
+        let policy = heap.lock().policy();
+        let result = run_trace(heap);
+        serial_println!();
+        serial_println!(
+            "  {:?}: {} cycles, fragmentation peak {}% final {}%",
+            policy,
+            result.cycles,
+            result.peak_fragmentation,
+            result.final_fragmentation
+        );
+        // 不管用哪种策略，全部释放后堆都能合并回一整块
+        let heap = heap.lock();
+        assert_eq!(heap.free_regions(), 1);
+        assert_eq!(heap.stats().fragmentation(), 0);
+    }
+}
@@ -0,0 +1,122 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::allocator::slab::SlabCache;
+use blog_os::memory::{self, bitmap::BitmapFrameAllocator};
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::install(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+fn free_frames() -> usize {
+    memory::with_kernel_memory(|_, frame_allocator| frame_allocator.free_frames()).unwrap()
+}
+
+/// 测试用的对象，按 64 字节对齐
+#[repr(align(64))]
+struct Object {
+    id: u64,
+    payload: [u8; 40],
+}
+
+static OBJECTS: SlabCache<Object> = SlabCache::new("object");
+
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+struct Counted(u32);
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        DROPPED.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test_case]
+fn objects_are_aligned_and_distinct() {
+    let a = OBJECTS
+        .alloc(Object {
+            id: 1,
+            payload: [1; 40],
+        })
+        .unwrap();
+    let b = OBJECTS
+        .alloc(Object {
+            id: 2,
+            payload: [2; 40],
+        })
+        .unwrap();
+    let (pa, pb) = (&*a as *const Object as usize, &*b as *const Object as usize);
+    assert_eq!(pa % 64, 0);
+    assert_eq!(pb % 64, 0);
+    assert_ne!(pa, pb);
+    assert_eq!(a.id + b.id, 3);
+    assert_eq!(a.payload[39] + b.payload[0], 3);
+}
+
+#[test_case]
+fn cache_grows_and_shrinks() {
+    OBJECTS.shrink();
+    let free_before = free_frames();
+    let per_slab = OBJECTS.stats().objects_per_slab;
+    let mut objects = [const { None }; 200];
+    for (i, slot) in objects.iter_mut().enumerate() {
+        *slot = OBJECTS.alloc(Object {
+            id: i as u64,
+            payload: [0; 40],
+        });
+    }
+    let stats = OBJECTS.stats();
+    assert_eq!(stats.name, "object");
+    assert_eq!(stats.active_objects, 200);
+    assert_eq!(stats.slabs, 200usize.div_ceil(per_slab));
+    assert!(stats.total_objects >= 200);
+    for (i, object) in objects.iter().enumerate() {
+        assert_eq!(object.as_ref().unwrap().id, i as u64);
+    }
+
+    // 释放一半后，slab 仍然被部分使用，不能回收
+    for slot in objects.iter_mut().step_by(2) {
+        *slot = None;
+    }
+    assert_eq!(OBJECTS.stats().active_objects, 100);
+    assert_eq!(OBJECTS.shrink(), 0);
+
+    // 全部释放后，所有 slab 都还给帧分配器
+    drop(objects);
+    assert_eq!(OBJECTS.shrink(), stats.slabs);
+    assert_eq!(OBJECTS.stats().slabs, 0);
+    assert_eq!(free_frames(), free_before);
+}
+
+#[test_case]
+fn constructor_and_destructor_run() {
+    let cache = SlabCache::with_constructor("counted", || Counted(7));
+    let dropped = DROPPED.load(Ordering::SeqCst);
+    let object = cache.alloc_constructed().unwrap();
+    assert_eq!(object.0, 7);
+    drop(object);
+    assert_eq!(DROPPED.load(Ordering::SeqCst), dropped + 1);
+    assert_eq!(cache.stats().active_objects, 0);
+}
@@ -2,11 +2,22 @@
 #![no_main]
 #![feature(abi_x86_interrupt)]
 
+use blog_os::memory::{
+    self,
+    bitmap::BitmapFrameAllocator,
+    stack::{self, KernelStack},
+};
 use blog_os::{QemuExitCode, exit_qemu, serial_print, serial_println};
+use bootloader::{BootInfo, entry_point};
+use conquer_once::spin::OnceCell;
 use core::panic::PanicInfo;
 use lazy_static::lazy_static;
+use x86_64::VirtAddr;
+use x86_64::registers::control::Cr2;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
 
+entry_point!(main);
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     blog_os::test_panic_handler(info)
@@ -29,22 +40,60 @@ pub fn init_test_idt() {
     TEST_IDT.load();
 }
 
-#[unsafe(no_mangle)]
-pub extern "C" fn _start() -> ! {
+// 要溢出的带保护页的栈
+static TEST_STACK: OnceCell<KernelStack> = OnceCell::uninit();
+
+fn main(boot_info: &'static BootInfo) -> ! {
     serial_print!("stack_overflow::stack_overflow...\t");
     blog_os::gdt::init();
     init_test_idt();
 
-    stack_overflow();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::install(mapper, frame_allocator); // double fault 也会切换到带保护页的栈上处理
 
+    let stack = stack::alloc_stack(4).expect("failed to allocate test stack");
+    TEST_STACK.init_once(|| stack);
+    // 切换到带保护页的栈上，然后让它溢出
+    unsafe {
+        core::arch::asm!(
+            "mov rsp, {top}",
+            "call {entry}",
+            top = in(reg) stack.top().as_u64(),
+            entry = sym overflow_entry,
+            options(noreturn),
+        );
+    }
+}
+
+extern "C" fn overflow_entry() -> ! {
+    stack_overflow();
     panic!("Execution continued after stack overflow");
 }
 
-// 双重故障处理函数，用于测试栈溢出引发的双重故障异常
+// 双重故障处理函数，检查栈溢出能否被诊断出来
 extern "x86-interrupt" fn test_double_fault_handler(
     _stack_frame: InterruptStackFrame,
     _err_code: u64,
 ) -> ! {
+    // 触发错误的地址必须位于测试栈的保护页中
+    let hit = stack::guard_page_hit(Cr2::read());
+    assert_eq!(
+        hit,
+        TEST_STACK.get().copied(),
+        "guard page hit not detected"
+    );
+    // 处理函数本身必须运行在带保护页的 IST 栈上
+    let rsp: u64;
+    unsafe { core::arch::asm!("mov {}, rsp", out(reg) rsp) };
+    let ist = blog_os::gdt::double_fault_stack().unwrap();
+    assert!(
+        ist.contains(VirtAddr::new(rsp)),
+        "double fault not on guarded IST stack"
+    );
+
     serial_println!("[ok]");
     exit_qemu(QemuExitCode::Success);
     loop {}
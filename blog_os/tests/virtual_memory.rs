@@ -0,0 +1,105 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::memory::{
+    self,
+    bitmap::BitmapFrameAllocator,
+    vma::{AreaKind, VirtualMemoryManager, VmError},
+};
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use spin::Mutex;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{OffsetPageTable, PageTableFlags, Translate};
+
+entry_point!(main);
+
+static MAPPER: Mutex<Option<OffsetPageTable<'static>>> = Mutex::new(None);
+static FRAME_ALLOCATOR: Mutex<Option<BitmapFrameAllocator>> = Mutex::new(None);
+
+// 测试用的虚拟地址区间，和内核虚拟地址空间不重叠
+const TEST_START: u64 = 0x_5555_0000_0000;
+const TEST_END: u64 = 0x_5555_0010_0000;
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    *MAPPER.lock() = Some(mapper);
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+fn test_manager() -> VirtualMemoryManager {
+    VirtualMemoryManager::new(VirtAddr::new(TEST_START), VirtAddr::new(TEST_END))
+}
+
+#[test_case]
+fn areas_do_not_overlap() {
+    let mut vmm = test_manager();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let a = vmm.reserve(3 * 4096, AreaKind::Heap, flags).unwrap();
+    let b = vmm.reserve(100, AreaKind::Stack, flags).unwrap();
+    let c = vmm.reserve(8 * 4096, AreaKind::Mmio, flags).unwrap();
+    assert_eq!(b.size, 4096);
+    assert!(a.end() <= b.start && b.end() <= c.start);
+    assert_eq!(vmm.find(b.start + 10u64), Some(b));
+    assert_eq!(vmm.find(c.end()), None);
+}
+
+#[test_case]
+fn released_gap_is_reused() {
+    let mut vmm = test_manager();
+    let flags = PageTableFlags::PRESENT;
+    let a = vmm.reserve(4 * 4096, AreaKind::Other, flags).unwrap();
+    let _b = vmm.reserve(4096, AreaKind::Other, flags).unwrap();
+    vmm.release(a.start).unwrap();
+    let c = vmm.reserve(2 * 4096, AreaKind::Other, flags).unwrap();
+    assert_eq!(c.start, a.start);
+    assert!(matches!(
+        vmm.release(a.start + 4096u64),
+        Err(VmError::AreaNotFound)
+    ));
+    assert!(matches!(
+        vmm.reserve(TEST_END - TEST_START, AreaKind::Other, flags),
+        Err(VmError::OutOfVirtualSpace)
+    ));
+}
+
+#[test_case]
+fn map_protect_unmap() {
+    let mut vmm = test_manager();
+    let mut mapper_guard = MAPPER.lock();
+    let mapper = mapper_guard.as_mut().unwrap();
+    let mut allocator_guard = FRAME_ALLOCATOR.lock();
+    let allocator = allocator_guard.as_mut().unwrap();
+
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let area = vmm.reserve(4 * 4096, AreaKind::Other, flags).unwrap();
+    vmm.map(area.start, mapper, allocator).unwrap();
+    for page in area.pages() {
+        unsafe { page.start_address().as_mut_ptr::<u64>().write_volatile(42) };
+    }
+
+    let read_only = PageTableFlags::PRESENT;
+    unsafe { vmm.protect(area.start, read_only, mapper).unwrap() };
+    assert_eq!(vmm.find(area.start).unwrap().flags, read_only);
+
+    let free_before = allocator.free_frames();
+    unsafe { vmm.unmap(area.start, mapper, allocator).unwrap() };
+    assert_eq!(allocator.free_frames(), free_before + 4);
+    assert_eq!(mapper.translate_addr(area.start), None);
+    vmm.release(area.start).unwrap();
+}
@@ -0,0 +1,112 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(blog_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use blog_os::memory::{
+    self,
+    bitmap::BitmapFrameAllocator,
+    vma::VmError,
+    vmalloc::{self, vfree, vmalloc},
+};
+use bootloader::{BootInfo, entry_point};
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, Translate};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    blog_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::install(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    blog_os::test_panic_handler(info)
+}
+
+fn free_frames() -> usize {
+    memory::with_kernel_memory(|_, frame_allocator| frame_allocator.free_frames()).unwrap()
+}
+
+fn phys_of(addr: VirtAddr) -> u64 {
+    memory::with_kernel_memory(|mapper, _| mapper.translate_addr(addr))
+        .unwrap()
+        .unwrap()
+        .as_u64()
+}
+
+#[test_case]
+fn large_buffer_is_usable() {
+    let size = 4 * 1024 * 1024;
+    let buffer = vmalloc(size).unwrap();
+    assert_eq!(vmalloc::vsize(buffer), Some(size));
+    let words = buffer.as_mut_ptr::<u64>();
+    let count = size / 8;
+    for i in (0..count).step_by(512) {
+        unsafe { words.add(i).write_volatile(i as u64) };
+    }
+    for i in (0..count).step_by(512) {
+        assert_eq!(unsafe { words.add(i).read_volatile() }, i as u64);
+    }
+    unsafe { vfree(buffer).unwrap() };
+    assert_eq!(vmalloc::vsize(buffer), None);
+}
+
+#[test_case]
+fn works_with_fragmented_physical_memory() {
+    // 先分配一批帧，再隔一个释放一个，让空闲的物理内存变得零碎
+    let mut frames = [None; 256];
+    memory::with_kernel_memory(|_, frame_allocator| {
+        for slot in frames.iter_mut() {
+            *slot = frame_allocator.allocate_frame();
+        }
+        for frame in frames.iter().step_by(2) {
+            unsafe { frame_allocator.deallocate_frame(frame.unwrap()) };
+        }
+    })
+    .unwrap();
+
+    let buffer = vmalloc(64 * 4096).unwrap();
+    // 虚拟上相邻的页面映射到了不相邻的物理帧
+    let scattered =
+        (0..63u64).any(|i| phys_of(buffer + (i + 1) * 4096) != phys_of(buffer + i * 4096) + 4096);
+    assert!(scattered);
+    unsafe { buffer.as_mut_ptr::<u8>().write_bytes(0xab, 64 * 4096) };
+    unsafe { vfree(buffer).unwrap() };
+
+    memory::with_kernel_memory(|_, frame_allocator| {
+        for frame in frames.iter().skip(1).step_by(2) {
+            unsafe { frame_allocator.deallocate_frame(frame.unwrap()) };
+        }
+    })
+    .unwrap();
+}
+
+#[test_case]
+fn vfree_returns_frames() {
+    let buffer = vmalloc(16 * 4096).unwrap(); // 先让需要的页表都建好
+    unsafe { vfree(buffer).unwrap() };
+    let free_before = free_frames();
+    let buffer = vmalloc(16 * 4096).unwrap();
+    assert_eq!(free_frames(), free_before - 16);
+    unsafe { vfree(buffer).unwrap() };
+    assert_eq!(free_frames(), free_before);
+}
+
+#[test_case]
+fn vfree_rejects_unknown_address() {
+    let buffer = vmalloc(4096).unwrap();
+    let result = unsafe { vfree(buffer + 4096u64) };
+    assert!(matches!(result, Err(VmError::AreaNotFound)));
+    unsafe { vfree(buffer).unwrap() };
+}